@@ -5,9 +5,15 @@ use self::vish::io::InputReader;
 use self::vish::environment::ShellEnvironment;
 
 fn main() -> ExitCode {
+    let mut args = std::env::args_os().skip(1).map(|arg| arg.to_string_lossy().into_owned());
+    if let Some(script) = args.next() {
+        return vish::app::handle_script_file(script, args.collect(),
+            ShellEnvironment::new());
+    }
+
     match InputReader::new() {
         Ok(mut reader) => vish::app::handle_interactive_mode(&mut reader,
             ShellEnvironment::new()),
-        Err(_) => vish::app::handle_batch_mode(),
+        Err(_) => vish::app::handle_batch_mode(ShellEnvironment::new()),
     }
 }
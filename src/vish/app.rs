@@ -1,19 +1,20 @@
 use std::process::ExitCode;
+use std::fs;
 use std::io::{self, Write};
 
 use super::io::InputReader;
 use super::buffer::Buffer;
-use super::command as cmd;
-use super::environment::ShellEnvironment as Env;
-use super::string::parse_argv;
+use super::environment::{ShellEnvironment as Env, Interrupt};
+use super::executor::execute;
+use super::executor::process::reap_children;
+use super::parser::{parse, Parser, ParseError};
 
 fn cleanup_input(reader: &mut InputReader) -> io::Result<()> {
     reader.disable_raw_mode()
 }
 
 fn draw_prompt(stdout: &mut io::Stdout, env: &Env, key: &str) -> io::Result<()> {
-    let unset_value = String::new();
-    let prompt = env.shell_variables.get(key).unwrap_or(&unset_value);
+    let prompt = env.get_var(key).unwrap_or_default();
     stdout.write_all(prompt.as_bytes())?;
     stdout.flush()
 }
@@ -23,7 +24,8 @@ fn draw_newline(stdout: &mut io::Stdout) -> io::Result<()> {
     stdout.flush()
 }
 
-pub fn handle_interactive_mode(reader: &mut InputReader, env: Env) -> ExitCode {
+pub fn handle_interactive_mode(reader: &mut InputReader, mut env: Env) -> ExitCode {
+    env.interactive = true;
     let mut stdout = io::stdout();
 
     macro_rules! draw_prompt {
@@ -45,9 +47,10 @@ pub fn handle_interactive_mode(reader: &mut InputReader, env: Env) -> ExitCode {
     }
 
     let mut buffer = Buffer::new();
-    let mut last_cmd_code: u8 = 0;
     let mut should_clear_buffer = true;
+    let mut incomplete_msg = String::new();
     let exit_code: u8 = loop {
+        reap_children();
         if should_clear_buffer {
             draw_prompt!("PS1");
             buffer.clear();
@@ -59,9 +62,10 @@ pub fn handle_interactive_mode(reader: &mut InputReader, env: Env) -> ExitCode {
             Ok(None) => {
                 if should_clear_buffer {
                     draw_newline!();
-                    break last_cmd_code;
+                    break env.last_status;
                 } else {
-                    eprintln!("vish: Syntax error: Unterminated quoted string");
+                    draw_newline!();
+                    eprintln!("vish: {}", incomplete_msg);
                     should_clear_buffer = true;
                     continue;
                 }
@@ -69,46 +73,50 @@ pub fn handle_interactive_mode(reader: &mut InputReader, env: Env) -> ExitCode {
             Err(e) => { eprintln!("{}", e); return 1.into(); },
         }
 
-        let (argv, quote_char) = match buffer.as_str() {
-            Ok(text) => parse_argv(text),
+        let parse_result = match buffer.as_str() {
+            Ok(text) => parse(text),
             Err(e) => {
                 eprintln!("{}", e);
                 return 1.into();
             }
         };
 
-        if argv.is_empty() {
-            draw_newline!();
-            continue;
-        }
+        draw_newline!();
 
-       match quote_char {
-            Some('\'') | Some('"') => {
-                match buffer.write(b"\n") {
-                    Ok(_) => {},
-                    Err(_) => { return 1.into(); }
-                };
-                draw_newline!();
+        let commands = match parse_result {
+            Ok(commands) => commands,
+            Err(e @ ParseError::Incomplete(_)) => {
+                if buffer.write(b"\n").is_err() {
+                    return 1.into();
+                }
+                incomplete_msg = e.to_string();
                 should_clear_buffer = false;
                 continue;
             },
-            Some(_) => { return 1.into(); },
-            None => { should_clear_buffer = true; }
-        }
-
-        draw_newline!();
-
-        last_cmd_code = match argv[0].as_str() {
-            "cd" => cmd::cd(argv),
-            "pwd" => cmd::pwd(argv),
-            "printf" => cmd::printf(argv),
-            "echo" => cmd::echo(argv),
-            "exec" => cmd::exec(argv, reader),
-            "exit" => { break cmd::exit(argv, last_cmd_code); },
-            "true" => 0,
-            "false" => 1,
-            _ => cmd::run_command(argv),
+            Err(e) => {
+                eprintln!("vish: {}", e);
+                env.last_status = 2;
+                should_clear_buffer = true;
+                continue;
+            },
         };
+        should_clear_buffer = true;
+
+        if reader.disable_raw_mode().is_err() {
+            eprintln!("vish: failed to restore terminal settings");
+        }
+        for list in &commands {
+            execute(list, &mut env);
+            if env.interrupt == Some(Interrupt::Exit) {
+                break;
+            }
+        }
+        if env.interrupt == Some(Interrupt::Exit) {
+            break env.last_status;
+        }
+        if reader.enable_raw_mode().is_err() {
+            return handle_fallback_mode();
+        }
     };
 
     match cleanup_input(reader) {
@@ -117,7 +125,30 @@ pub fn handle_interactive_mode(reader: &mut InputReader, env: Env) -> ExitCode {
     }
 }
 
-pub fn handle_batch_mode() -> ExitCode {
+// Parse and run one complete command at a time, like a script is read
+fn run_script(text: &str, env: &mut Env) -> u8 {
+    let mut parser = Parser::new(text);
+    let mut status = 0;
+
+    loop {
+        match parser.complete_command() {
+            Ok(Some(list)) => {
+                reap_children();
+                status = execute(&list, env);
+                if env.interrupt == Some(Interrupt::Exit) {
+                    return status;
+                }
+            },
+            Ok(None) => return status,
+            Err(e) => {
+                eprintln!("vish: {}", e);
+                return 2;
+            },
+        }
+    }
+}
+
+pub fn handle_batch_mode(mut env: Env) -> ExitCode {
     let mut input_lines = Vec::new();
 
     for line in io::stdin().lines() {
@@ -130,8 +161,23 @@ pub fn handle_batch_mode() -> ExitCode {
         }
     }
 
-    // read and execute commands from script
-    0.into()
+    run_script(&input_lines.join("\n"), &mut env).into()
+}
+
+// Run the script file, with the rest of the arguments as positional
+// parameters
+pub fn handle_script_file(path: String, args: Vec<String>, mut env: Env) -> ExitCode {
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("vish: cannot open {}: {}", path, e);
+            return 127.into();
+        }
+    };
+
+    env.shell_name = path;
+    env.positional_parameters = args;
+    run_script(&text, &mut env).into()
 }
 
 pub fn handle_fallback_mode() -> ExitCode {
@@ -1,44 +1,39 @@
 use std::process::Command;
-use std::os::unix::process::CommandExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::io::ErrorKind::{NotFound, PermissionDenied, InvalidInput};
-use std::env::{self, set_current_dir, current_dir};
+use std::env::{set_current_dir, current_dir};
 use std::cmp::Ordering;
 use std::ffi::OsString;
 use std::fmt::format;
 use std::path::PathBuf;
 
 use super::buffer::Buffer;
-use super::io::InputReader;
+use super::environment::{ShellEnvironment as Env, Interrupt, Variable};
+use super::executor::process::status_code;
+use super::parser::is_name;
 
 
 pub type ArgV = Vec<String>;
-pub enum ShellCommand {
-    SpBuiltin(String),
-    Builtin(String),
-    Function(String),
-    Alias(String),
-    Process(String),
-    Script(String),
-    Variable(String, String),
-}
 
 macro_rules! error_msg {
     ($err:ident) => { $err.to_string().split(" (").collect::<Vec<_>>()[0] }
 }
+pub(crate) use error_msg;
 
-pub fn run_command(argv: ArgV) -> u8 {
+pub fn run_command(argv: ArgV, env: &Env) -> u8 {
     let size = argv.len();
     let mut command = Command::new(argv[0].clone());
     if size > 1 {
         command.args(argv[1..].to_vec());
     }
+    command.env_clear().envs(env.exported_vars());
     match command.status() {
-        Ok(status) => status.code().unwrap_or(1) as u8,
-        Err(_) => 1,
+        Ok(status) => status_code(status.into_raw()),
+        Err(e) => report_exec_error(&argv[0], e),
     }
 }
 
-pub fn cd(argv: ArgV) -> u8 {
+pub fn cd(argv: ArgV, env: &mut Env) -> u8 {
     match argv.len().cmp(&2) {
         Ordering::Greater => {
             eprintln!("vish: cd: too many arguments");
@@ -56,12 +51,12 @@ pub fn cd(argv: ArgV) -> u8 {
                 }
             }
             if argv[1] == "-" {
-                match env::var("OLDPWD") {
-                    Ok(old_pwd) => {
+                match env.get_var("OLDPWD") {
+                    Some(old_pwd) => {
                         path_buf.clear();
                         path_buf.push(old_pwd);
                     },
-                    Err(_) => {
+                    None => {
                         eprintln!("vish: cd: OLDPWD is not set");
                         return 1;
                     }
@@ -85,17 +80,18 @@ pub fn cd(argv: ArgV) -> u8 {
                 eprintln!("vish: cd: {} - {}", name, error_msg!(e));
                 1
             } else {
-                env::set_var("PWD", path);
-                env::set_var("OLDPWD", cur_dir.as_path());
+                // Like other shells, cd still succeeds when PWD is read only
+                let _ = env.set_var("PWD", path.to_string_lossy().into_owned());
+                let _ = env.set_var("OLDPWD", cur_dir.to_string_lossy().into_owned());
                 0
             }
         },
         Ordering::Less => {
-            match env::var("HOME") {
-                Ok(home) => {
+            match env.get_var("HOME") {
+                Some(home) => {
                     if set_current_dir(home).is_err() { 1 } else { 0 }
                 },
-                Err(_) => {
+                None => {
                     let msg = "vish: cd: HOME environment variable is not set";
                     eprintln!("{}", msg);
                     1
@@ -121,14 +117,6 @@ pub fn pwd(_argv: ArgV) -> u8 {
     0
 }
 
-pub fn parse_command<E>(name: &str) -> Result<ShellCommand, E> {
-    if let Some((n, v)) = name.split_once('=') {
-        Ok(ShellCommand::Variable(String::from(n), String::from(v)))
-    } else {
-        Ok(ShellCommand::Process(String::from("exit")))
-    }
-}
-
 pub fn exit(argv: ArgV, exit_code: u8) -> u8 {
     if argv.len() < 2 {
         return exit_code;
@@ -142,6 +130,251 @@ pub fn exit(argv: ArgV, exit_code: u8) -> u8 {
     }
 }
 
+fn loop_count(argv: &ArgV) -> Option<usize> {
+    match argv.get(1).map(|arg| arg.parse::<usize>()) {
+        None => Some(1),
+        Some(Ok(count)) if count > 0 => Some(count),
+        _ => {
+            eprintln!("vish: {}: Illegal number: {}", argv[0], argv[1]);
+            None
+        },
+    }
+}
+
+pub fn break_loop(argv: ArgV, env: &mut Env) -> u8 {
+    match loop_count(&argv) {
+        Some(count) => {
+            if env.loop_depth > 0 {
+                env.interrupt = Some(Interrupt::Break(count.min(env.loop_depth)));
+            }
+            0
+        },
+        None => 1,
+    }
+}
+
+pub fn continue_loop(argv: ArgV, env: &mut Env) -> u8 {
+    match loop_count(&argv) {
+        Some(count) => {
+            if env.loop_depth > 0 {
+                env.interrupt = Some(Interrupt::Continue(count.min(env.loop_depth)));
+            }
+            0
+        },
+        None => 1,
+    }
+}
+
+// Special builtins are found before functions of the same name
+pub fn is_special_builtin(name: &str) -> bool {
+    matches!(name, "break" | ":" | "continue" | "exec" | "exit" | "export" |
+        "readonly" | "return" | "set" | "shift" | "unset")
+}
+
+pub fn return_function(argv: ArgV, env: &mut Env) -> u8 {
+    if !env.in_function() {
+        eprintln!("vish: return: not in a function");
+        return 1;
+    }
+    let status = match argv.get(1).map(|arg| arg.parse::<i64>()) {
+        None => env.last_status,
+        Some(Ok(status)) => status as u8,
+        Some(Err(_)) => {
+            eprintln!("vish: return: Illegal number: {}", argv[1]);
+            2
+        },
+    };
+    env.interrupt = Some(Interrupt::Return);
+    status
+}
+
+pub fn local(argv: ArgV, env: &mut Env) -> u8 {
+    if !env.in_function() {
+        eprintln!("vish: local: not in a function");
+        return 1;
+    }
+    for arg in &argv[1..] {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !is_name(name) {
+            eprintln!("vish: local: {}: bad variable name", name);
+            return 1;
+        }
+        env.make_local(name);
+        if let Some(value) = value {
+            if let Err(msg) = env.set_var(name, String::from(value)) {
+                eprintln!("vish: local: {}", msg);
+                return 1;
+            }
+        }
+    }
+    0
+}
+
+// Options that can be turned on with -x and off with +x. Only noglob is
+// supported for now
+const OPTIONS: &str = "f";
+
+// Quote the value so that it can be read back by the shell
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+pub fn set(argv: ArgV, env: &mut Env) -> u8 {
+    if argv.len() < 2 {
+        let mut variables: Vec<_> = env.shell_variables.iter()
+            .filter_map(|(name, variable)| Some((name, variable.value.as_ref()?)))
+            .collect();
+        variables.sort();
+        for (name, value) in variables {
+            println!("{}={}", name, quote(value));
+        }
+        return 0;
+    }
+
+    let mut args = argv.into_iter().skip(1).peekable();
+    while let Some(arg) = args.next_if(|arg| arg.starts_with(['-', '+'])) {
+        // The operands after -- replace the positional parameters, even if
+        // there are none
+        if arg == "--" || arg == "-" {
+            env.positional_parameters = args.collect();
+            return 0;
+        }
+        if let Some(option) = arg.chars().skip(1).find(|&option| !OPTIONS.contains(option)) {
+            eprintln!("vish: set: Illegal option {}{}", &arg[..1], option);
+            return 2;
+        }
+        let enable = arg.starts_with('-');
+        for option in arg.chars().skip(1) {
+            if enable {
+                env.options.insert(option);
+            } else {
+                env.options.remove(&option);
+            }
+        }
+    }
+
+    if args.peek().is_some() {
+        env.positional_parameters = args.collect();
+    }
+    0
+}
+
+// Print the variables having the attribute, in a way that can be read back
+fn print_attribute(command: &str, has_attribute: fn(&Variable) -> bool, env: &Env) {
+    let mut variables: Vec<_> = env.shell_variables.iter()
+        .filter(|(_, variable)| has_attribute(variable))
+        .collect();
+    variables.sort_by_key(|(name, _)| *name);
+    for (name, variable) in variables {
+        match &variable.value {
+            Some(value) => println!("{} {}={}", command, name, quote(value)),
+            None => println!("{} {}", command, name),
+        }
+    }
+}
+
+// Shared by export and readonly, which give attributes to the variables
+// named by the arguments, assigning them a value after =
+fn set_attribute(argv: ArgV, has_attribute: fn(&Variable) -> bool,
+    add_attribute: fn(&mut Env, &str), env: &mut Env) -> u8 {
+    let command = argv[0].as_str();
+    let operands = match argv.get(1).map(String::as_str) {
+        Some("-p") => &argv[2..],
+        Some("--") => &argv[2..],
+        Some(option) if option.starts_with('-') && option != "-" => {
+            eprintln!("vish: {}: Illegal option {}", command, option);
+            return 2;
+        },
+        _ => &argv[1..],
+    };
+    if operands.is_empty() {
+        print_attribute(command, has_attribute, env);
+        return 0;
+    }
+
+    let mut status = 0;
+    for arg in operands {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !is_name(name) {
+            eprintln!("vish: {}: {}: bad variable name", command, name);
+            status = 1;
+            continue;
+        }
+        if let Some(value) = value {
+            if let Err(msg) = env.set_var(name, String::from(value)) {
+                eprintln!("vish: {}: {}", command, msg);
+                status = 1;
+                continue;
+            }
+        }
+        add_attribute(env, name);
+    }
+    status
+}
+
+pub fn export(argv: ArgV, env: &mut Env) -> u8 {
+    set_attribute(argv, |variable| variable.exported, Env::export_var, env)
+}
+
+pub fn readonly(argv: ArgV, env: &mut Env) -> u8 {
+    set_attribute(argv, |variable| variable.readonly, Env::make_readonly, env)
+}
+
+// Unset variables, or functions with -f
+pub fn unset(argv: ArgV, env: &mut Env) -> u8 {
+    let mut functions = false;
+    let mut operands = &argv[1..];
+    while let Some(arg) = operands.first().filter(|arg| arg.starts_with('-') && *arg != "-") {
+        operands = &operands[1..];
+        if arg == "--" {
+            break;
+        }
+        for option in arg.chars().skip(1) {
+            match option {
+                'f' => functions = true,
+                'v' => functions = false,
+                _ => {
+                    eprintln!("vish: unset: Illegal option -{}", option);
+                    return 2;
+                },
+            }
+        }
+    }
+
+    let mut status = 0;
+    for name in operands {
+        if functions {
+            env.functions.remove(name);
+        } else if let Err(msg) = env.unset_var(name) {
+            eprintln!("vish: unset: {}", msg);
+            status = 1;
+        }
+    }
+    status
+}
+
+pub fn shift(argv: ArgV, env: &mut Env) -> u8 {
+    let n = match argv.get(1).map(|arg| arg.parse::<usize>()) {
+        None => 1,
+        Some(Ok(n)) => n,
+        Some(Err(_)) => {
+            eprintln!("vish: shift: Illegal number: {}", argv[1]);
+            return 2;
+        },
+    };
+    if !env.shift_parameters(n) {
+        eprintln!("vish: shift: can't shift that many");
+        return 1;
+    }
+    0
+}
+
 pub fn printf(argv: ArgV) -> u8 {
     if argv.len() > 1 {
         for arg in &argv[1..] {
@@ -166,33 +399,32 @@ pub fn echo(argv: ArgV) -> u8 {
     0
 }
 
-pub fn exec(argv: ArgV, reader: &mut InputReader) -> u8 {
+pub fn exec(argv: ArgV, env: &Env) -> u8 {
     if argv.len() < 2 {
         eprintln!("vish: exec: no command passed to exec");
         return 1;
-    } else if reader.disable_raw_mode().is_err() {
-        eprintln!("vish: exec: failed to cleanup environment...");
-        return 1;
     }
 
     let err = Command::new(argv[1].clone())
         .args(&argv[2..])
+        .env_clear()
+        .envs(env.exported_vars())
         .exec();
-    if reader.enable_raw_mode().is_err() {
-        eprintln!("warning: failed to reactivate raw mode");
-    }
+    report_exec_error(&argv[1], err)
+}
 
+fn report_exec_error(name: &str, err: std::io::Error) -> u8 {
     match err.kind() {
         NotFound => {
-            eprintln!("vish: {}: Not found", argv[1]);
+            eprintln!("vish: {}: Not found", name);
             127
         },
         PermissionDenied => {
-            eprintln!("vish: {}: Permission denied", argv[1]);
+            eprintln!("vish: {}: Permission denied", name);
             126
         },
         InvalidInput => {
-            eprintln!("vish: {}: Invalid input", argv[1]);
+            eprintln!("vish: {}: Invalid input", name);
             1
         },
         _ => {
@@ -240,6 +472,46 @@ fn replace_escape_sequence(input: &[u8]) -> Vec<u8> {
 }
 
 #[cfg(test)]
+mod set {
+    use super::set;
+    use crate::vish::environment::ShellEnvironment as Env;
+
+    fn argv(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| String::from(*arg)).collect()
+    }
+
+    #[test]
+    fn toggle_noglob() {
+        let mut env = Env::new();
+        assert_eq!(set(argv(&["set", "-f"]), &mut env), 0);
+        assert!(env.options.contains(&'f'));
+        assert_eq!(set(argv(&["set", "+f"]), &mut env), 0);
+        assert!(!env.options.contains(&'f'));
+    }
+
+    #[test]
+    fn reject_unsupported_options() {
+        let mut env = Env::new();
+        for option in ["-e", "-u", "-x", "-C", "+e"] {
+            assert_eq!(set(argv(&["set", option]), &mut env), 2);
+        }
+        assert_eq!(set(argv(&["set", "-fe", "a"]), &mut env), 2);
+        assert!(env.options.is_empty());
+        assert!(env.positional_parameters.is_empty());
+    }
+
+    #[test]
+    fn replace_positional_parameters() {
+        let mut env = Env::new();
+        assert_eq!(set(argv(&["set", "-f", "a", "b"]), &mut env), 0);
+        assert_eq!(env.positional_parameters, argv(&["a", "b"]));
+        assert_eq!(set(argv(&["set", "--"]), &mut env), 0);
+        assert!(env.positional_parameters.is_empty());
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::items_after_test_module)]
 mod replace_escape_sequence {
     use super::replace_escape_sequence;
 
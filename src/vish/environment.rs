@@ -1,25 +1,201 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, BTreeSet};
 use std::env;
+use std::ffi::OsString;
+use std::rc::Rc;
 
-type ShVar = HashMap<String, String>;
+use super::parser::{CompoundCommand, Redirect};
+
+type ShVar = HashMap<String, Variable>;
+pub type Function = Rc<(CompoundCommand, Vec<Redirect>)>;
+
+// Entry of the variable table. An exported variable may have no value yet,
+// and it is only passed to child processes once it is given one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Variable {
+    pub value: Option<String>,
+    pub exported: bool,
+    pub readonly: bool,
+}
+
+// Request to leave the normal flow of execution, raised by special builtins
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    Exit,
+    Break(usize),
+    Continue(usize),
+    Return,
+}
 
 pub struct ShellEnvironment {
+    // Name of the shell or script, expanded by $0
+    pub shell_name: String,
+    // Process ID of the shell itself, which subshells keep for $$
+    pub shell_pid: u32,
     pub shell_variables: ShVar,
+    // Arguments of the script or of the function being run, expanded by $1,
+    // $2 and so on
+    pub positional_parameters: Vec<String>,
+    // Positional parameters of the callers, put back when a function returns
+    parameter_stack: Vec<Vec<String>>,
+    pub functions: HashMap<String, Function>,
+    // Values that local variables had before each function call, to be put
+    // back once the function returns
+    local_scopes: Vec<HashMap<String, Option<Variable>>>,
+    pub last_status: u8,
+    // Status of the last command substitution, which a command made only of
+    // assignments and redirections returns
+    pub substitution_status: Option<u8>,
+    pub interactive: bool,
+    // Letters of the options turned on with set, like f for noglob
+    pub options: BTreeSet<char>,
+    // Set when an expansion fails, so that the command using it isn't run
+    pub expansion_failed: bool,
+    pub last_background_pid: Option<i32>,
+    pub loop_depth: usize,
+    pub interrupt: Option<Interrupt>,
 }
 
 impl ShellEnvironment {
     pub fn new() -> Self {
-        let mut shell_variables = HashMap::new();
+        let mut shell_variables = Self::import_vars(env::vars_os());
         Self::init_par(&mut shell_variables, "PS1", "$ ");
         Self::init_par(&mut shell_variables, "PS2", "> ");
+        // An inherited IFS would change how scripts split words, so the shell
+        // always starts with the default one
+        shell_variables.entry(String::from("IFS")).or_default().value = Some(String::from(" \t\n"));
+
+        Self {
+            shell_name: env::args_os().next()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .unwrap_or_else(|| String::from("vish")),
+            shell_pid: std::process::id(),
+            shell_variables,
+            positional_parameters: Vec::new(),
+            parameter_stack: Vec::new(),
+            functions: HashMap::new(),
+            local_scopes: Vec::new(),
+            last_status: 0,
+            substitution_status: None,
+            interactive: false,
+            options: BTreeSet::new(),
+            expansion_failed: false,
+            last_background_pid: None,
+            loop_depth: 0,
+            interrupt: None,
+        }
+    }
+
+    pub fn get_var(&self, name: &str) -> Option<String> {
+        self.shell_variables.get(name)?.value.clone()
+    }
+
+    // Give the variable a value, keeping its attributes. The error is the
+    // message to show when the variable is read only.
+    pub fn set_var(&mut self, name: &str, value: String) -> Result<(), String> {
+        let variable = self.shell_variables.entry(String::from(name)).or_default();
+        if variable.readonly {
+            return Err(format!("{}: is read only", name));
+        }
+        variable.value = Some(value);
+        Ok(())
+    }
+
+    pub fn unset_var(&mut self, name: &str) -> Result<(), String> {
+        if self.shell_variables.get(name).is_some_and(|variable| variable.readonly) {
+            return Err(format!("{}: is read only", name));
+        }
+        self.shell_variables.remove(name);
+        Ok(())
+    }
+
+    pub fn export_var(&mut self, name: &str) {
+        self.shell_variables.entry(String::from(name)).or_default().exported = true;
+    }
+
+    pub fn make_readonly(&mut self, name: &str) {
+        self.shell_variables.entry(String::from(name)).or_default().readonly = true;
+    }
+
+    // Exported variables that have a value, which make up the environment of
+    // child processes
+    pub fn exported_vars(&self) -> Vec<(&str, &str)> {
+        self.shell_variables
+            .iter()
+            .filter(|(_, variable)| variable.exported)
+            .filter_map(|(name, variable)| Some((name.as_str(), variable.value.as_deref()?)))
+            .collect()
+    }
+
+    // Option letters as expanded by $-
+    pub fn option_flags(&self) -> String {
+        let mut flags: String = self.options.iter().collect();
+        if self.interactive {
+            flags.push('i');
+        }
+        flags
+    }
+
+    pub fn push_parameters(&mut self, parameters: Vec<String>) {
+        let saved = std::mem::replace(&mut self.positional_parameters, parameters);
+        self.parameter_stack.push(saved);
+    }
+
+    pub fn pop_parameters(&mut self) {
+        if let Some(parameters) = self.parameter_stack.pop() {
+            self.positional_parameters = parameters;
+        }
+    }
+
+    // Drop the first n positional parameters, failing when there are fewer
+    pub fn shift_parameters(&mut self, n: usize) -> bool {
+        if n > self.positional_parameters.len() {
+            return false;
+        }
+        self.positional_parameters.drain(..n);
+        true
+    }
+
+    pub fn in_function(&self) -> bool {
+        !self.local_scopes.is_empty()
+    }
+
+    pub fn push_scope(&mut self) {
+        self.local_scopes.push(HashMap::new());
+    }
 
-        Self { shell_variables }
+    pub fn pop_scope(&mut self) {
+        let Some(scope) = self.local_scopes.pop() else { return };
+        for (name, value) in scope {
+            match value {
+                Some(variable) => self.shell_variables.insert(name, variable),
+                None => self.shell_variables.remove(&name),
+            };
+        }
+    }
+
+    // Remember the current value of the variable, so that changes made from
+    // now on are undone when the current function returns
+    pub fn make_local(&mut self, name: &str) {
+        let Some(scope) = self.local_scopes.last_mut() else { return };
+        if !scope.contains_key(name) {
+            scope.insert(String::from(name), self.shell_variables.get(name).cloned());
+        }
+    }
+
+    // Variables inherited from the environment, which stay exported. Entries
+    // that aren't valid UTF-8 can't be used by the shell, so they are left out
+    fn import_vars(vars: impl Iterator<Item = (OsString, OsString)>) -> ShVar {
+        vars.filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .map(|(name, value)| {
+                let variable = Variable { value: Some(value), exported: true, readonly: false };
+                (name, variable)
+            })
+            .collect()
     }
 
     fn init_par(shell_variables: &mut ShVar, key: &str, default: &str) {
-        shell_variables.insert(
-            String::from(key),
-            env::var(String::from(key)).unwrap_or(String::from(default)));
+        let variable = shell_variables.entry(String::from(key)).or_default();
+        variable.value.get_or_insert_with(|| String::from(default));
     }
 }
 
@@ -28,3 +204,111 @@ impl Default for ShellEnvironment {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod pop_scope {
+    use super::ShellEnvironment as Env;
+
+    #[test]
+    fn restore_local_variables() {
+        let mut env = Env::new();
+        env.set_var("outer", String::from("global")).unwrap();
+        env.push_scope();
+        env.make_local("outer");
+        env.make_local("inner");
+        env.set_var("outer", String::from("local")).unwrap();
+        env.set_var("inner", String::from("local")).unwrap();
+        env.make_local("outer");
+        assert_eq!(env.get_var("outer"), Some(String::from("local")));
+
+        env.pop_scope();
+        assert_eq!(env.get_var("outer"), Some(String::from("global")));
+        assert_eq!(env.shell_variables.get("inner"), None);
+        assert!(!env.in_function());
+    }
+}
+
+#[cfg(test)]
+mod set_var {
+    use super::ShellEnvironment as Env;
+
+    #[test]
+    fn refuse_to_change_readonly_variables() {
+        let mut env = Env::new();
+        env.set_var("fixed", String::from("old")).unwrap();
+        env.make_readonly("fixed");
+        let error = Err(String::from("fixed: is read only"));
+        assert_eq!(env.set_var("fixed", String::from("new")), error);
+        assert_eq!(env.unset_var("fixed"), error);
+        assert_eq!(env.get_var("fixed"), Some(String::from("old")));
+    }
+
+    #[test]
+    fn export_only_variables_with_values() {
+        let mut env = Env::new();
+        env.export_var("pending");
+        env.set_var("shared", String::from("value")).unwrap();
+        env.export_var("shared");
+        env.set_var("private", String::from("value")).unwrap();
+
+        let exported = env.exported_vars();
+        assert!(exported.contains(&("shared", "value")));
+        assert!(!exported.iter().any(|(name, _)| *name == "pending" || *name == "private"));
+    }
+}
+
+#[cfg(test)]
+mod shift_parameters {
+    use super::ShellEnvironment as Env;
+
+    fn parameters(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| String::from(*value)).collect()
+    }
+
+    #[test]
+    fn shift_and_restore_parameters() {
+        let mut env = Env::new();
+        env.positional_parameters = parameters(&["script", "args"]);
+        env.push_parameters(parameters(&["a", "b", "c"]));
+        assert!(env.shift_parameters(2));
+        assert_eq!(env.positional_parameters, parameters(&["c"]));
+        assert!(!env.shift_parameters(2));
+        assert_eq!(env.positional_parameters, parameters(&["c"]));
+
+        env.pop_parameters();
+        assert_eq!(env.positional_parameters, parameters(&["script", "args"]));
+    }
+}
+
+#[cfg(test)]
+mod import_vars {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+
+    use super::ShellEnvironment as Env;
+
+    #[test]
+    fn skip_variables_that_are_not_utf8() {
+        let vars = vec![
+            (OsString::from("GOOD"), OsString::from("value")),
+            (OsString::from("BAD"), OsString::from_vec(vec![0xff])),
+            (OsString::from_vec(vec![b'X', 0xff]), OsString::from("value")),
+        ];
+        let variables = Env::import_vars(vars.into_iter());
+        assert_eq!(variables.len(), 1);
+        assert_eq!(variables["GOOD"].value.as_deref(), Some("value"));
+        assert!(variables["GOOD"].exported);
+    }
+}
+
+#[cfg(test)]
+mod new {
+    use super::ShellEnvironment as Env;
+
+    #[test]
+    fn reset_inherited_ifs() {
+        std::env::set_var("IFS", ":");
+        let env = Env::new();
+        assert_eq!(env.get_var("IFS"), Some(String::from(" \t\n")));
+    }
+}
@@ -0,0 +1,870 @@
+use std::rc::Rc;
+
+use super::parser::{
+    List, AndOr, LogicalOp, Pipeline, Command, SimpleCommand, CompoundCommand,
+    CaseItem, Redirect,
+};
+use super::command::{self as cmd, ArgV};
+use super::environment::{ShellEnvironment as Env, Interrupt, Function};
+use super::string::{expand_word, expand_words, expand_pattern};
+use super::pattern::Pattern;
+
+pub mod process;
+mod redirect;
+use redirect::apply_redirects;
+
+use process::{fork, exit_child, wait_for, pipe, move_fd, close, open_null_input};
+
+// Consume the error left by an expansion. A shell that isn't interactive
+// exits, as the command it was about to run can't be performed.
+fn expansion_failed(env: &mut Env) -> bool {
+    if !env.expansion_failed {
+        return false;
+    }
+    env.expansion_failed = false;
+    if !env.interactive {
+        env.interrupt = Some(Interrupt::Exit);
+    }
+    true
+}
+
+// An assignment to a read only variable is treated like a failed expansion
+fn assignment_failed(msg: &str, env: &mut Env) -> u8 {
+    eprintln!("vish: {}", msg);
+    env.expansion_failed = true;
+    expansion_failed(env);
+    2
+}
+
+pub fn execute(list: &List, env: &mut Env) -> u8 {
+    let mut status = env.last_status;
+    for item in &list.items {
+        status = if item.asynchronous {
+            run_asynchronous(&item.and_or, env)
+        } else {
+            execute_and_or(&item.and_or, env)
+        };
+        env.last_status = status;
+        if env.interrupt.is_some() {
+            break;
+        }
+    }
+    status
+}
+
+// Start the list in a child process without waiting for it. There is no job
+// control, so the child doesn't read from the terminal nor get its signals.
+fn run_asynchronous(and_or: &AndOr, env: &mut Env) -> u8 {
+    match fork() {
+        Ok(None) => {
+            unsafe {
+                libc::signal(libc::SIGINT, libc::SIG_IGN);
+                libc::signal(libc::SIGQUIT, libc::SIG_IGN);
+            }
+            if let Err(e) = open_null_input() {
+                eprintln!("vish: /dev/null: {}", e);
+                exit_child(1);
+            }
+            exit_child(execute_and_or(and_or, env));
+        },
+        Ok(Some(pid)) => {
+            env.last_background_pid = Some(pid);
+            0
+        },
+        Err(e) => {
+            eprintln!("vish: cannot fork: {}", e);
+            1
+        },
+    }
+}
+
+// Each pipeline after && or || only runs depending on the previous status
+fn execute_and_or(and_or: &AndOr, env: &mut Env) -> u8 {
+    let mut status = execute_pipeline(&and_or.first, env);
+
+    for (op, pipeline) in &and_or.rest {
+        if env.interrupt.is_some() {
+            break;
+        }
+        let should_run = match op {
+            LogicalOp::And => status == 0,
+            LogicalOp::Or => status != 0,
+        };
+        if should_run {
+            env.last_status = status;
+            status = execute_pipeline(pipeline, env);
+        }
+    }
+    status
+}
+
+fn execute_pipeline(pipeline: &Pipeline, env: &mut Env) -> u8 {
+    let status = if pipeline.commands.len() == 1 {
+        execute_command(&pipeline.commands[0], env)
+    } else {
+        run_pipeline(&pipeline.commands, env)
+    };
+
+    if pipeline.bang {
+        (status == 0) as u8
+    } else {
+        status
+    }
+}
+
+// Run every command in its own child process, connecting each command's
+// standard output to the standard input of the next one
+fn run_pipeline(commands: &[Command], env: &mut Env) -> u8 {
+    let mut pids = Vec::with_capacity(commands.len());
+    let mut input = None;
+
+    for (i, command) in commands.iter().enumerate() {
+        let output = if i + 1 < commands.len() {
+            match pipe() {
+                Ok(fds) => Some(fds),
+                Err(e) => {
+                    eprintln!("vish: cannot create pipe: {}", e);
+                    break;
+                },
+            }
+        } else {
+            None
+        };
+
+        match fork() {
+            Ok(None) => {
+                // Descriptors of a new pipe can always be moved to 0 and 1
+                if let Some(fd) = input {
+                    let _ = move_fd(fd, 0);
+                }
+                if let Some((read_end, write_end)) = output {
+                    close(read_end);
+                    let _ = move_fd(write_end, 1);
+                }
+                exit_child(execute_command(command, env));
+            },
+            Ok(Some(pid)) => pids.push(pid),
+            Err(e) => eprintln!("vish: cannot fork: {}", e),
+        }
+
+        if let Some(fd) = input {
+            close(fd);
+        }
+        input = output.map(|(read_end, write_end)| {
+            close(write_end);
+            read_end
+        });
+    }
+
+    if let Some(fd) = input {
+        close(fd);
+    }
+
+    let mut status = 1;
+    for pid in pids {
+        status = wait_for(pid);
+    }
+    status
+}
+
+fn execute_command(command: &Command, env: &mut Env) -> u8 {
+    match command {
+        Command::Simple(simple) => execute_simple_command(simple, env),
+        Command::Compound(compound, redirects) => {
+            execute_compound_command(compound, redirects, env)
+        },
+        Command::FunctionDefinition(function) => {
+            env.functions.insert(function.name.clone(), Rc::clone(&function.body));
+            0
+        },
+    }
+}
+
+fn execute_compound_command(compound: &CompoundCommand, redirects: &[Redirect],
+    env: &mut Env) -> u8 {
+    let saved_fds = match apply_redirects(redirects, env) {
+        Ok(saved_fds) => saved_fds,
+        Err(msg) => {
+            eprintln!("vish: {}", msg);
+            return 1;
+        },
+    };
+    if expansion_failed(env) {
+        saved_fds.restore();
+        return 2;
+    }
+
+    let status = match compound {
+        CompoundCommand::If { conditionals, else_part } => {
+            execute_if(conditionals, else_part.as_ref(), env)
+        },
+        CompoundCommand::While { condition, body } => {
+            execute_loop(condition, body, false, env)
+        },
+        CompoundCommand::Until { condition, body } => {
+            execute_loop(condition, body, true, env)
+        },
+        CompoundCommand::For { name, words, body } => {
+            execute_for(name, words.as_deref(), body, env)
+        },
+        CompoundCommand::Case { word, items } => execute_case(word, items, env),
+        CompoundCommand::BraceGroup(list) => execute(list, env),
+        CompoundCommand::Subshell(list) => run_subshell(list, env),
+    };
+
+    saved_fds.restore();
+    status
+}
+
+// The child gets a copy of the whole shell environment, so changes made by
+// the list, including exit, don't affect the shell itself
+fn run_subshell(list: &List, env: &mut Env) -> u8 {
+    match fork() {
+        Ok(None) => exit_child(execute(list, env)),
+        Ok(Some(pid)) => wait_for(pid),
+        Err(e) => {
+            eprintln!("vish: cannot fork: {}", e);
+            1
+        },
+    }
+}
+
+// Run the body of the first branch whose condition succeeds
+fn execute_if(conditionals: &[(List, List)], else_part: Option<&List>,
+    env: &mut Env) -> u8 {
+    for (condition, body) in conditionals {
+        let status = execute(condition, env);
+        if env.interrupt.is_some() {
+            return status;
+        }
+        if status == 0 {
+            return execute(body, env);
+        }
+    }
+
+    match else_part {
+        Some(body) => execute(body, env),
+        None => 0,
+    }
+}
+
+// Consume a pending break or continue aimed at the innermost loop and
+// return true if that loop must stop
+fn loop_interrupted(env: &mut Env) -> bool {
+    match env.interrupt {
+        Some(Interrupt::Break(count)) => {
+            env.interrupt = if count > 1 { Some(Interrupt::Break(count - 1)) } else { None };
+            true
+        },
+        Some(Interrupt::Continue(count)) if count > 1 => {
+            env.interrupt = Some(Interrupt::Continue(count - 1));
+            true
+        },
+        Some(Interrupt::Continue(_)) => {
+            env.interrupt = None;
+            false
+        },
+        Some(Interrupt::Exit) | Some(Interrupt::Return) => true,
+        None => false,
+    }
+}
+
+fn execute_loop(condition: &List, body: &List, until: bool, env: &mut Env) -> u8 {
+    let mut status = 0;
+    env.loop_depth += 1;
+
+    loop {
+        let condition_status = execute(condition, env);
+        if env.interrupt.is_some() {
+            if !loop_interrupted(env) {
+                continue;
+            }
+            // Leaving the loop because of exit or return
+            if env.interrupt.is_some() {
+                status = condition_status;
+            }
+            break;
+        }
+        if (condition_status == 0) == until {
+            break;
+        }
+
+        status = execute(body, env);
+        if env.interrupt.is_some() && loop_interrupted(env) {
+            break;
+        }
+    }
+
+    env.loop_depth -= 1;
+    status
+}
+
+// Run the body of the first item with a pattern matching the word
+fn execute_case(word: &str, items: &[CaseItem], env: &mut Env) -> u8 {
+    let word = expand_word(word, env);
+    if expansion_failed(env) {
+        return 2;
+    }
+
+    for item in items {
+        for pattern in &item.patterns {
+            let pattern = expand_pattern(pattern, env);
+            if expansion_failed(env) {
+                return 2;
+            }
+            if Pattern::new(&pattern).matches(&word) {
+                return match &item.body {
+                    Some(body) => execute(body, env),
+                    None => 0,
+                };
+            }
+        }
+    }
+    0
+}
+
+// Without a word list, the loop goes over the positional parameters
+fn execute_for(name: &str, words: Option<&[String]>, body: &List, env: &mut Env) -> u8 {
+    let values = match words {
+        Some(words) => expand_words(words, env),
+        None => env.positional_parameters.clone(),
+    };
+    if expansion_failed(env) {
+        return 2;
+    }
+    let mut status = 0;
+    env.loop_depth += 1;
+
+    for value in values {
+        if let Err(msg) = env.set_var(name, value) {
+            status = assignment_failed(&msg, env);
+            break;
+        }
+        status = execute(body, env);
+        if env.interrupt.is_some() && loop_interrupted(env) {
+            break;
+        }
+    }
+
+    env.loop_depth -= 1;
+    status
+}
+
+// Expand and assign the values from left to right, so that a value can
+// refer to a variable assigned before it
+fn assign(assignments: &[String], env: &mut Env) -> u8 {
+    for (name, value) in assignments.iter().filter_map(|word| word.split_once('=')) {
+        let value = expand_word(value, env);
+        if expansion_failed(env) {
+            return 2;
+        }
+        if let Err(msg) = env.set_var(name, value) {
+            return assignment_failed(&msg, env);
+        }
+    }
+    0
+}
+
+// Assignments before a command that isn't a special builtin are exported to
+// it, and only last while it runs
+fn run_with_assignments(argv: ArgV, assignments: &[String], env: &mut Env) -> u8 {
+    let saved: Vec<_> = assignments
+        .iter()
+        .filter_map(|word| word.split_once('='))
+        .map(|(name, _)| (String::from(name), env.shell_variables.get(name).cloned()))
+        .collect();
+    for (name, _) in &saved {
+        env.export_var(name);
+    }
+
+    let status = match assign(assignments, env) {
+        0 => run_simple_command(argv, env),
+        status => status,
+    };
+
+    for (name, variable) in saved.into_iter().rev() {
+        match variable {
+            Some(variable) => env.shell_variables.insert(name, variable),
+            None => env.shell_variables.remove(&name),
+        };
+    }
+    status
+}
+
+fn execute_simple_command(command: &SimpleCommand, env: &mut Env) -> u8 {
+    env.substitution_status = None;
+    let argv: ArgV = expand_words(&command.words, env);
+    if expansion_failed(env) {
+        return 2;
+    }
+    let saved_fds = match apply_redirects(&command.redirects, env) {
+        Ok(saved_fds) => saved_fds,
+        Err(msg) => {
+            eprintln!("vish: {}", msg);
+            return 1;
+        },
+    };
+    if expansion_failed(env) {
+        saved_fds.restore();
+        return 2;
+    }
+
+    if argv.len() == 1 && argv[0] == "exec" {
+        saved_fds.discard();
+        return assign(&command.assignments, env);
+    }
+
+    // Without a command name, the assignments change the shell itself and
+    // the status is the one of the last command substitution
+    let status = if argv.is_empty() {
+        match assign(&command.assignments, env) {
+            0 => env.substitution_status.unwrap_or(0),
+            status => status,
+        }
+    } else if cmd::is_special_builtin(&argv[0]) && argv[0] != "exec" {
+        // The assignments of special builtins stay in the shell, except for
+        // exec with a utility, which gets them exported like other commands
+        match assign(&command.assignments, env) {
+            0 => run_simple_command(argv, env),
+            status => status,
+        }
+    } else if command.assignments.is_empty() {
+        run_simple_command(argv, env)
+    } else {
+        run_with_assignments(argv, &command.assignments, env)
+    };
+    saved_fds.restore();
+    status
+}
+
+// The function gets the arguments as positional parameters, and break or
+// continue inside it don't reach the loops of the caller
+fn call_function(function: &Function, argv: ArgV, env: &mut Env) -> u8 {
+    env.push_parameters(argv.into_iter().skip(1).collect());
+    let saved_loop_depth = std::mem::replace(&mut env.loop_depth, 0);
+    env.push_scope();
+
+    let status = execute_compound_command(&function.0, &function.1, env);
+    if env.interrupt == Some(Interrupt::Return) {
+        env.interrupt = None;
+    }
+
+    env.pop_scope();
+    env.loop_depth = saved_loop_depth;
+    env.pop_parameters();
+    status
+}
+
+fn run_simple_command(argv: ArgV, env: &mut Env) -> u8 {
+    if !cmd::is_special_builtin(&argv[0]) {
+        if let Some(function) = env.functions.get(&argv[0]).cloned() {
+            return call_function(&function, argv, env);
+        }
+    }
+
+    match argv[0].as_str() {
+        "cd" => cmd::cd(argv, env),
+        "pwd" => cmd::pwd(argv),
+        "printf" => cmd::printf(argv),
+        "echo" => cmd::echo(argv),
+        "exec" => cmd::exec(argv, env),
+        "exit" => {
+            env.interrupt = Some(Interrupt::Exit);
+            cmd::exit(argv, env.last_status)
+        },
+        "break" => cmd::break_loop(argv, env),
+        "continue" => cmd::continue_loop(argv, env),
+        "return" => cmd::return_function(argv, env),
+        "local" => cmd::local(argv, env),
+        "set" => cmd::set(argv, env),
+        "shift" => cmd::shift(argv, env),
+        "export" => cmd::export(argv, env),
+        "readonly" => cmd::readonly(argv, env),
+        "unset" => cmd::unset(argv, env),
+        ":" | "true" => 0,
+        "false" => 1,
+        _ => cmd::run_command(argv, env),
+    }
+}
+
+// Parse the script and run its commands one after the other, like the shell
+// does for a script file
+#[cfg(test)]
+fn run_script(script: &str, env: &mut Env) -> u8 {
+    let mut status = 0;
+    for list in super::parser::parse(script).unwrap() {
+        status = execute(&list, env);
+        if env.interrupt.is_some() {
+            break;
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod execute_pipeline {
+    use super::run_script;
+    use crate::vish::environment::ShellEnvironment as Env;
+
+    #[test]
+    fn return_status_of_last_command() {
+        let mut env = Env::new();
+        assert_eq!(run_script("true | false", &mut env), 1);
+        assert_eq!(run_script("false | true", &mut env), 0);
+        assert_eq!(run_script("false | exit 3 | true | false", &mut env), 1);
+    }
+
+    #[test]
+    fn negate_status() {
+        let mut env = Env::new();
+        assert_eq!(run_script("! true", &mut env), 1);
+        assert_eq!(run_script("! false", &mut env), 0);
+        assert_eq!(run_script("! false | true", &mut env), 1);
+    }
+
+    #[test]
+    fn run_commands_in_child_processes() {
+        let mut env = Env::new();
+        assert_eq!(run_script("x=1 | true; exit 4 | true", &mut env), 0);
+        assert_eq!(env.get_var("x"), None);
+        assert_eq!(env.interrupt, None);
+    }
+}
+
+#[cfg(test)]
+mod execute_and_or {
+    use super::run_script;
+    use crate::vish::environment::ShellEnvironment as Env;
+
+    #[test]
+    fn skip_pipelines_after_and_on_failure() {
+        let mut env = Env::new();
+        assert_eq!(run_script("true && false", &mut env), 1);
+        assert_eq!(run_script("false && x=1", &mut env), 1);
+        assert_eq!(env.get_var("x"), None);
+    }
+
+    #[test]
+    fn skip_pipelines_after_or_on_success() {
+        let mut env = Env::new();
+        assert_eq!(run_script("false || true", &mut env), 0);
+        assert_eq!(run_script("true || x=1", &mut env), 0);
+        assert_eq!(env.get_var("x"), None);
+    }
+
+    #[test]
+    fn evaluate_from_left_to_right() {
+        let mut env = Env::new();
+        assert_eq!(run_script("false && x=1 || y=2", &mut env), 0);
+        assert_eq!(env.get_var("x"), None);
+        assert_eq!(env.get_var("y"), Some(String::from("2")));
+        assert_eq!(run_script("true || x=1 && false", &mut env), 1);
+        assert_eq!(env.get_var("x"), None);
+    }
+}
+
+#[cfg(test)]
+mod execute {
+    use super::{run_script, process::wait_for};
+    use crate::vish::environment::ShellEnvironment as Env;
+
+    #[test]
+    fn run_commands_in_order() {
+        let mut env = Env::new();
+        assert_eq!(run_script("x=1; y=$x; false", &mut env), 1);
+        assert_eq!(env.get_var("y"), Some(String::from("1")));
+        assert_eq!(run_script("false; true", &mut env), 0);
+    }
+
+    #[test]
+    fn run_asynchronous_lists_in_background() {
+        let mut env = Env::new();
+        assert_eq!(run_script("x=1 && exit 3 &", &mut env), 0);
+        assert_eq!(env.get_var("x"), None);
+        assert_eq!(env.interrupt, None);
+
+        let pid = env.last_background_pid.expect("background pid");
+        assert_eq!(wait_for(pid), 3);
+        assert_eq!(run_script("false & last=$!", &mut env), 0);
+        let pid = env.last_background_pid.expect("background pid");
+        assert_eq!(env.get_var("last"), Some(pid.to_string()));
+        assert_eq!(wait_for(pid), 1);
+    }
+}
+
+#[cfg(test)]
+mod execute_if {
+    use super::run_script;
+    use crate::vish::environment::ShellEnvironment as Env;
+
+    #[test]
+    fn run_first_branch_whose_condition_succeeds() {
+        let mut env = Env::new();
+        let script = "if false; then x=1; elif true; then x=2; else x=3; fi";
+        assert_eq!(run_script(script, &mut env), 0);
+        assert_eq!(env.get_var("x"), Some(String::from("2")));
+        run_script("if false; then x=1; else x=3; fi", &mut env);
+        assert_eq!(env.get_var("x"), Some(String::from("3")));
+    }
+
+    #[test]
+    fn return_status_of_branch() {
+        let mut env = Env::new();
+        assert_eq!(run_script("if true; then false; fi", &mut env), 1);
+        assert_eq!(run_script("if false; then true; fi", &mut env), 0);
+        assert_eq!(run_script("if false; then :; else false; fi", &mut env), 1);
+    }
+}
+
+#[cfg(test)]
+mod execute_loop {
+    use super::run_script;
+    use crate::vish::environment::ShellEnvironment as Env;
+
+    #[test]
+    fn repeat_while_condition_holds() {
+        let mut env = Env::new();
+        let script = "i=0; while case $i in 3) false;; esac; do i=$((i + 1)); done";
+        assert_eq!(run_script(script, &mut env), 0);
+        assert_eq!(env.get_var("i"), Some(String::from("3")));
+
+        let script = "until case $i in 0) true;; *) false;; esac; do i=$((i - 1)); done";
+        assert_eq!(run_script(script, &mut env), 0);
+        assert_eq!(env.get_var("i"), Some(String::from("0")));
+        assert_eq!(run_script("while false; do :; done", &mut env), 0);
+    }
+
+    #[test]
+    fn break_out_of_enclosing_loops() {
+        let mut env = Env::new();
+        let script = "out=; for i in 1 2; do for j in a b c; do \
+            case $j in b) break 2;; esac; out=$out$i$j; done; out=${out}x; done";
+        assert_eq!(run_script(script, &mut env), 0);
+        assert_eq!(env.get_var("out"), Some(String::from("1a")));
+        assert_eq!(env.interrupt, None);
+        assert_eq!(env.loop_depth, 0);
+    }
+
+    #[test]
+    fn continue_with_enclosing_loops() {
+        let mut env = Env::new();
+        let script = "out=; for i in 1 2; do for j in a b c; do \
+            case $j in b) continue 2;; esac; out=$out$i$j; done; out=${out}x; done";
+        assert_eq!(run_script(script, &mut env), 0);
+        assert_eq!(env.get_var("out"), Some(String::from("1a2a")));
+        assert_eq!(env.interrupt, None);
+    }
+
+    #[test]
+    fn limit_count_to_enclosing_loops() {
+        let mut env = Env::new();
+        let script = "while true; do break 5; done; x=after";
+        assert_eq!(run_script(script, &mut env), 0);
+        assert_eq!(env.get_var("x"), Some(String::from("after")));
+        assert_eq!(run_script("for i in 1; do break 0; done", &mut env), 1);
+    }
+
+    #[test]
+    fn ignore_break_outside_loops() {
+        let mut env = Env::new();
+        assert_eq!(run_script("break; continue; x=1", &mut env), 0);
+        assert_eq!(env.get_var("x"), Some(String::from("1")));
+        assert_eq!(env.interrupt, None);
+    }
+}
+
+#[cfg(test)]
+mod execute_for {
+    use super::run_script;
+    use crate::vish::environment::ShellEnvironment as Env;
+
+    #[test]
+    fn iterate_over_expanded_words() {
+        let mut env = Env::new();
+        let script = "list='b c'; out=; for i in a $list \"d e\"; do out=$out[$i]; done";
+        assert_eq!(run_script(script, &mut env), 0);
+        assert_eq!(env.get_var("out"), Some(String::from("[a][b][c][d e]")));
+        assert_eq!(env.get_var("i"), Some(String::from("d e")));
+    }
+
+    #[test]
+    fn iterate_over_positional_parameters() {
+        let mut env = Env::new();
+        let script = "set -- x 'y z'; out=; for i; do out=$out[$i]; done";
+        assert_eq!(run_script(script, &mut env), 0);
+        assert_eq!(env.get_var("out"), Some(String::from("[x][y z]")));
+    }
+
+    #[test]
+    fn return_status_of_last_iteration() {
+        let mut env = Env::new();
+        assert_eq!(run_script("for i in a b; do false; done", &mut env), 1);
+        assert_eq!(run_script("false; for i in; do false; done", &mut env), 0);
+    }
+}
+
+#[cfg(test)]
+mod execute_case {
+    use super::run_script;
+    use crate::vish::environment::ShellEnvironment as Env;
+
+    #[test]
+    fn run_first_matching_item() {
+        let mut env = Env::new();
+        let script = "word=main.rs; case $word in \
+            *.c) x=c;; *.rs | *.toml) x=rust;; *) x=other;; esac";
+        assert_eq!(run_script(script, &mut env), 0);
+        assert_eq!(env.get_var("x"), Some(String::from("rust")));
+    }
+
+    #[test]
+    fn match_quoted_patterns_literally() {
+        let mut env = Env::new();
+        run_script("case abc in \"a*\") x=quoted;; a*) x=pattern;; esac", &mut env);
+        assert_eq!(env.get_var("x"), Some(String::from("pattern")));
+        run_script("case 'a*' in \"a*\") x=quoted;; esac", &mut env);
+        assert_eq!(env.get_var("x"), Some(String::from("quoted")));
+    }
+
+    #[test]
+    fn return_status_of_matching_item() {
+        let mut env = Env::new();
+        assert_eq!(run_script("case a in a) false;; esac", &mut env), 1);
+        assert_eq!(run_script("false; case a in b) false;; esac", &mut env), 0);
+    }
+}
+
+#[cfg(test)]
+mod run_subshell {
+    use super::run_script;
+    use crate::vish::environment::ShellEnvironment as Env;
+
+    #[test]
+    fn keep_changes_inside_subshell() {
+        let mut env = Env::new();
+        assert_eq!(run_script("x=outer; (x=inner; y=1; f() { :; })", &mut env), 0);
+        assert_eq!(env.get_var("x"), Some(String::from("outer")));
+        assert_eq!(env.get_var("y"), None);
+        assert!(!env.functions.contains_key("f"));
+    }
+
+    #[test]
+    fn exit_only_from_subshell() {
+        let mut env = Env::new();
+        assert_eq!(run_script("(exit 3)", &mut env), 3);
+        assert_eq!(env.interrupt, None);
+        assert_eq!(run_script("(false) || x=1", &mut env), 0);
+        assert_eq!(env.get_var("x"), Some(String::from("1")));
+    }
+}
+
+#[cfg(test)]
+mod execute_compound_command {
+    use super::run_script;
+    use crate::vish::environment::{ShellEnvironment as Env, Interrupt};
+
+    #[test]
+    fn run_brace_group_in_current_shell() {
+        let mut env = Env::new();
+        assert_eq!(run_script("{ x=1; false; }", &mut env), 1);
+        assert_eq!(env.get_var("x"), Some(String::from("1")));
+        assert_eq!(run_script("{ false; } || { y=2; }", &mut env), 0);
+        assert_eq!(env.get_var("y"), Some(String::from("2")));
+    }
+
+    #[test]
+    fn exit_from_brace_group() {
+        let mut env = Env::new();
+        assert_eq!(run_script("{ exit 4; }; x=1", &mut env), 4);
+        assert_eq!(env.interrupt, Some(Interrupt::Exit));
+        assert_eq!(env.get_var("x"), None);
+    }
+}
+
+#[cfg(test)]
+mod call_function {
+    use super::run_script;
+    use crate::vish::environment::ShellEnvironment as Env;
+
+    #[test]
+    fn return_status_from_function() {
+        let mut env = Env::new();
+        assert_eq!(run_script("f() { return 3; x=1; }; f", &mut env), 3);
+        assert_eq!(env.interrupt, None);
+        assert_eq!(env.get_var("x"), None);
+        assert_eq!(run_script("f() { false; return; }; f", &mut env), 1);
+        assert_eq!(run_script("f() { return 3; }; f; y=$?", &mut env), 0);
+        assert_eq!(env.get_var("y"), Some(String::from("3")));
+    }
+
+    #[test]
+    fn restore_local_variables() {
+        let mut env = Env::new();
+        assert_eq!(run_script("v=outer; f() { local v=inner; w=$v; }; f", &mut env), 0);
+        assert_eq!(env.get_var("v"), Some(String::from("outer")));
+        assert_eq!(env.get_var("w"), Some(String::from("inner")));
+    }
+
+    #[test]
+    fn restore_positional_parameters() {
+        let mut env = Env::new();
+        assert_eq!(run_script("set -- a b; f() { p=$1$#; }; f x; q=$1$#", &mut env), 0);
+        assert_eq!(env.get_var("p"), Some(String::from("x1")));
+        assert_eq!(env.get_var("q"), Some(String::from("a2")));
+    }
+}
+
+#[cfg(test)]
+mod execute_simple_command {
+    use super::run_script;
+    use crate::vish::environment::{ShellEnvironment as Env, Interrupt};
+
+    #[test]
+    fn undo_prefix_assignments() {
+        let mut env = Env::new();
+        assert_eq!(run_script("x=old; x=new true", &mut env), 0);
+        assert_eq!(env.get_var("x"), Some(String::from("old")));
+        assert!(!env.shell_variables["x"].exported);
+        assert_eq!(run_script("f() { y=$x; }; x=new f", &mut env), 0);
+        assert_eq!(env.get_var("y"), Some(String::from("new")));
+        assert_eq!(env.get_var("x"), Some(String::from("old")));
+        assert!(!env.shell_variables["x"].exported);
+        assert_eq!(run_script("z=1 true", &mut env), 0);
+        assert_eq!(env.get_var("z"), None);
+    }
+
+    #[test]
+    fn keep_prefix_assignments_of_special_builtins() {
+        let mut env = Env::new();
+        assert_eq!(run_script("x=kept :", &mut env), 0);
+        assert_eq!(env.get_var("x"), Some(String::from("kept")));
+        assert_eq!(run_script("z=1 export w=2", &mut env), 0);
+        assert_eq!(env.get_var("z"), Some(String::from("1")));
+        assert_eq!(env.get_var("w"), Some(String::from("2")));
+    }
+
+    #[test]
+    fn export_prefix_assignments_of_exec() {
+        let mut env = Env::new();
+        let script = "(FOO=bar exec sh -c '[ \"$FOO\" = bar ]')";
+        assert_eq!(run_script(script, &mut env), 0);
+        assert_eq!(env.get_var("FOO"), None);
+    }
+
+    #[test]
+    fn return_status_of_command_substitution() {
+        let mut env = Env::new();
+        assert_eq!(run_script("x=$(false)", &mut env), 1);
+        assert_eq!(run_script("x=$(false) y=$(true)", &mut env), 0);
+        assert_eq!(run_script("x=1", &mut env), 0);
+    }
+
+    #[test]
+    fn fail_on_readonly_variable() {
+        let mut env = Env::new();
+        assert_eq!(run_script("readonly r=1; f() { x=1; }; r=2 f", &mut env), 2);
+        assert_eq!(env.interrupt, Some(Interrupt::Exit));
+        assert_eq!(env.get_var("r"), Some(String::from("1")));
+        assert_eq!(env.get_var("x"), None);
+    }
+}
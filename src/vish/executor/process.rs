@@ -0,0 +1,105 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::{RawFd, IntoRawFd};
+
+use libc::{self, c_int, pid_t};
+
+// Convert a status reported by waitpid into an exit code
+pub fn status_code(status: c_int) -> u8 {
+    if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status) as u8
+    } else if libc::WIFSIGNALED(status) {
+        (128 + libc::WTERMSIG(status)) as u8
+    } else {
+        1
+    }
+}
+
+// Fork the shell, returning None in the child and the child's pid in the
+// parent. Buffered output is flushed first so that it isn't written twice.
+pub fn fork() -> io::Result<Option<pid_t>> {
+    io::stdout().flush()?;
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            unsafe { libc::signal(libc::SIGPIPE, libc::SIG_DFL); }
+            Ok(None)
+        },
+        pid => Ok(Some(pid)),
+    }
+}
+
+// Terminate a forked child without running the parent's cleanup code
+pub fn exit_child(status: u8) -> ! {
+    let _ = io::stdout().flush();
+    unsafe { libc::_exit(status as c_int) }
+}
+
+pub fn wait_for(pid: pid_t) -> u8 {
+    let mut status: c_int = 0;
+    loop {
+        if unsafe { libc::waitpid(pid, &mut status, 0) } != -1 {
+            return status_code(status);
+        }
+        if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            return 1;
+        }
+    }
+}
+
+// Collect finished background children so they don't linger as zombies
+pub fn reap_children() {
+    let mut status: c_int = 0;
+    while unsafe { libc::waitpid(-1, &mut status, libc::WNOHANG) } > 0 {}
+}
+
+pub fn pipe() -> io::Result<(RawFd, RawFd)> {
+    let mut fds: [c_int; 2] = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok((fds[0], fds[1]))
+    }
+}
+
+// Make `target` refer to the same file as `fd` and close the original. The
+// descriptor is kept open across exec, even if it was already in place.
+pub fn move_fd(fd: RawFd, target: RawFd) -> io::Result<()> {
+    if fd == target {
+        unsafe { libc::fcntl(fd, libc::F_SETFD, 0); }
+        return Ok(());
+    }
+    let result = match unsafe { libc::dup2(fd, target) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    };
+    close(fd);
+    result
+}
+
+pub fn open_null_input() -> io::Result<()> {
+    let file = File::open("/dev/null")?;
+    move_fd(file.into_raw_fd(), 0)
+}
+
+pub fn close(fd: RawFd) {
+    unsafe { libc::close(fd); }
+}
+
+#[cfg(test)]
+mod status_code {
+    use super::status_code;
+
+    #[test]
+    fn decode_exit_status() {
+        assert_eq!(status_code(0), 0);
+        assert_eq!(status_code(1 << 8), 1);
+        assert_eq!(status_code(127 << 8), 127);
+    }
+
+    #[test]
+    fn decode_signal_status() {
+        assert_eq!(status_code(libc::SIGKILL), 137);
+        assert_eq!(status_code(libc::SIGINT), 130);
+    }
+}
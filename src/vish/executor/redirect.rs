@@ -0,0 +1,262 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write, Seek, SeekFrom};
+use std::os::unix::io::{RawFd, IntoRawFd};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::vish::command::error_msg;
+use crate::vish::environment::ShellEnvironment as Env;
+use crate::vish::parser::{Redirect, RedirectOp};
+use crate::vish::string::{expand_word, expand_here_doc};
+use super::process::{move_fd, close};
+
+// Saved copies of the descriptors are kept above the range used by scripts
+const SAVED_FD_BASE: RawFd = 10;
+
+// Descriptors replaced by redirections, along with a copy of what they
+// referred to before, or None if they weren't open
+pub struct SavedFds {
+    saved: Vec<(RawFd, Option<RawFd>)>,
+}
+
+impl SavedFds {
+    // Put the original descriptors back in place
+    pub fn restore(self) {
+        let _ = io::stdout().flush();
+        for (fd, copy) in self.saved.into_iter().rev() {
+            match copy {
+                Some(copy) => { let _ = move_fd(copy, fd); },
+                None => close(fd),
+            }
+        }
+    }
+
+    // Make the redirections permanent, as done by exec without a command
+    pub fn discard(self) {
+        for (_, copy) in self.saved {
+            if let Some(copy) = copy {
+                close(copy);
+            }
+        }
+    }
+
+    fn save(&mut self, fd: RawFd) {
+        if self.saved.iter().any(|(saved_fd, _)| *saved_fd == fd) {
+            return;
+        }
+        let copy = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, SAVED_FD_BASE) };
+        self.saved.push((fd, if copy == -1 { None } else { Some(copy) }));
+    }
+}
+
+fn default_fd(op: RedirectOp) -> RawFd {
+    match op {
+        RedirectOp::Input | RedirectOp::ReadWrite | RedirectOp::DupInput |
+        RedirectOp::HereDoc { .. } => 0,
+        RedirectOp::Output | RedirectOp::Append | RedirectOp::Clobber |
+        RedirectOp::DupOutput => 1,
+    }
+}
+
+fn is_open(fd: RawFd) -> bool {
+    unsafe { libc::fcntl(fd, libc::F_GETFD) != -1 }
+}
+
+fn open_file(path: &str, op: RedirectOp) -> Result<RawFd, String> {
+    let mut options = OpenOptions::new();
+    match op {
+        RedirectOp::Input => options.read(true),
+        RedirectOp::Output | RedirectOp::Clobber => {
+            options.write(true).create(true).truncate(true)
+        },
+        RedirectOp::Append => options.append(true).create(true),
+        _ => options.read(true).write(true).create(true),
+    };
+    match options.open(path) {
+        Ok(file) => Ok(file.into_raw_fd()),
+        Err(e) => Err(format!("cannot open {}: {}", path, error_msg!(e))),
+    }
+}
+
+// Store the here-document in an unlinked temporary file, so that bodies of
+// any size can be read without a process feeding a pipe
+fn here_doc_fd(body: &str) -> Result<RawFd, String> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = format!("vish-{}-{}", process::id(), COUNTER.fetch_add(1, Ordering::Relaxed));
+    let path = env::temp_dir().join(name);
+
+    let result = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .and_then(|mut file| {
+            fs::remove_file(&path)?;
+            file.write_all(body.as_bytes())?;
+            file.seek(SeekFrom::Start(0))?;
+            Ok(file.into_raw_fd())
+        });
+
+    result.map_err(|e| format!("cannot create here-document: {}", error_msg!(e)))
+}
+
+fn bad_descriptor(fd: RawFd, err: io::Error) -> String {
+    format!("{}: {}", fd, error_msg!(err))
+}
+
+// Put the newly opened file in place of the redirected descriptor
+fn replace_fd(new_fd: RawFd, fd: RawFd) -> Result<(), String> {
+    move_fd(new_fd, fd).map_err(|e| bad_descriptor(fd, e))
+}
+
+fn duplicate(source: &str, fd: RawFd) -> Result<(), String> {
+    if source == "-" {
+        close(fd);
+        return Ok(());
+    }
+    match source.parse::<RawFd>() {
+        Ok(source_fd) if is_open(source_fd) => {
+            if source_fd != fd && unsafe { libc::dup2(source_fd, fd) } == -1 {
+                return Err(bad_descriptor(fd, io::Error::last_os_error()));
+            }
+            Ok(())
+        },
+        _ => Err(format!("{}: Bad file descriptor", source)),
+    }
+}
+
+// Perform the redirections on the shell's own descriptors, saving the
+// previous ones so that builtins and external commands see the same thing
+pub fn apply_redirects(redirects: &[Redirect], env: &mut Env) -> Result<SavedFds, String> {
+    let mut saved_fds = SavedFds { saved: Vec::new() };
+    let _ = io::stdout().flush();
+
+    for redirect in redirects {
+        let fd = redirect.fd.unwrap_or_else(|| default_fd(redirect.op));
+        saved_fds.save(fd);
+
+        let result = match redirect.op {
+            RedirectOp::HereDoc { quoted } => {
+                let body = if quoted {
+                    redirect.target.clone()
+                } else {
+                    expand_here_doc(&redirect.target, env)
+                };
+                here_doc_fd(&body).and_then(|new_fd| replace_fd(new_fd, fd))
+            },
+            RedirectOp::DupInput | RedirectOp::DupOutput => {
+                duplicate(&expand_word(&redirect.target, env), fd)
+            },
+            op => {
+                open_file(&expand_word(&redirect.target, env), op)
+                    .and_then(|new_fd| replace_fd(new_fd, fd))
+            },
+        };
+
+        if let Err(msg) = result {
+            saved_fds.restore();
+            return Err(msg);
+        }
+    }
+
+    Ok(saved_fds)
+}
+
+#[cfg(test)]
+mod apply_redirects {
+    use super::{apply_redirects, is_open};
+    use crate::vish::environment::ShellEnvironment as Env;
+    use crate::vish::parser::{Redirect, RedirectOp};
+    use std::fs::{self, OpenOptions};
+    use std::os::unix::io::{RawFd, IntoRawFd};
+
+    fn redirect(fd: i32, op: RedirectOp, target: &str) -> Redirect {
+        Redirect { fd: Some(fd), op, target: String::from(target) }
+    }
+
+    // Descriptor opened by the test itself, so that redirecting it can't
+    // disturb the other tests running at the same time
+    fn owned_fd() -> RawFd {
+        OpenOptions::new().write(true).open("/dev/null").unwrap().into_raw_fd()
+    }
+
+    fn write_fd(fd: i32, text: &str) {
+        unsafe { libc::write(fd, text.as_ptr() as *const libc::c_void, text.len()); }
+    }
+
+    #[test]
+    fn redirect_and_restore_descriptor() {
+        let mut env = Env::new();
+        let path = std::env::temp_dir().join(format!("vish-redirect-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let (fd, copy) = (owned_fd(), owned_fd());
+
+        let saved = apply_redirects(&[redirect(fd, RedirectOp::Output, path)], &mut env).unwrap();
+        write_fd(fd, "first\n");
+        saved.restore();
+        write_fd(fd, "restored\n");
+
+        let saved = apply_redirects(&[
+            redirect(fd, RedirectOp::Append, path),
+            redirect(copy, RedirectOp::DupOutput, &fd.to_string()),
+        ], &mut env).unwrap();
+        write_fd(copy, "second\n");
+        saved.restore();
+        write_fd(copy, "restored\n");
+
+        assert_eq!(fs::read_to_string(path).unwrap(), "first\nsecond\n");
+        fs::remove_file(path).unwrap();
+        unsafe { libc::close(fd); libc::close(copy); }
+    }
+
+    #[test]
+    fn read_here_document() {
+        let mut env = Env::new();
+        let fd = owned_fd();
+        let body = "line one\nline two\n";
+        let here_doc = Redirect {
+            fd: Some(fd),
+            op: RedirectOp::HereDoc { quoted: true },
+            target: String::from(body),
+        };
+        let saved = apply_redirects(&[here_doc], &mut env).unwrap();
+        let mut buf = [0u8; 64];
+        let size = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        saved.restore();
+        assert_eq!(&buf[..size as usize], body.as_bytes());
+        unsafe { libc::close(fd); }
+    }
+
+    #[test]
+    fn reject_bad_descriptor() {
+        let mut env = Env::new();
+        let fd = owned_fd();
+        let result = apply_redirects(&[redirect(fd, RedirectOp::DupOutput, "-1")], &mut env);
+        assert_eq!(result.err(), Some(String::from("-1: Bad file descriptor")));
+        assert!(is_open(fd));
+        unsafe { libc::close(fd); }
+    }
+
+    #[test]
+    fn reject_descriptor_out_of_range() {
+        let mut env = Env::new();
+        let path = std::env::temp_dir().join(format!("vish-range-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let result = apply_redirects(&[redirect(99999, RedirectOp::Output, path)], &mut env);
+        assert_eq!(result.err(), Some(String::from("99999: Bad file descriptor")));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reject_missing_input_file() {
+        let mut env = Env::new();
+        let fd = owned_fd();
+        let result = apply_redirects(&[
+            redirect(fd, RedirectOp::Input, "/nonexistent/vish/input"),
+        ], &mut env);
+        assert!(result.is_err());
+        assert!(is_open(fd));
+        unsafe { libc::close(fd); }
+    }
+}
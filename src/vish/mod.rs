@@ -3,5 +3,8 @@ pub mod buffer;
 pub mod app;
 pub mod command;
 pub mod environment;
+pub mod executor;
+pub mod parser;
 pub mod passwd;
+pub mod pattern;
 pub mod string;
@@ -0,0 +1,576 @@
+use std::collections::VecDeque;
+
+use super::{ParseError, Parser, unexpected};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Word(String),
+    IoNumber(i32),
+    Operator(Operator),
+    // Replaces the delimiter word after << or <<- once the body is read
+    HereDoc { body: String, quoted: bool },
+    Newline,
+    Eof,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    AndIf,
+    OrIf,
+    DSemi,
+    DLessDash,
+    DLess,
+    DGreat,
+    LessAnd,
+    GreatAnd,
+    LessGreat,
+    Clobber,
+    Less,
+    Great,
+    Pipe,
+    Semi,
+    Amp,
+    LParen,
+    RParen,
+}
+
+// Longest operators come first so that matching is greedy
+const OPERATORS: [(&str, Operator); 17] = [
+    ("<<-", Operator::DLessDash),
+    ("&&", Operator::AndIf),
+    ("||", Operator::OrIf),
+    (";;", Operator::DSemi),
+    ("<<", Operator::DLess),
+    (">>", Operator::DGreat),
+    ("<&", Operator::LessAnd),
+    (">&", Operator::GreatAnd),
+    ("<>", Operator::LessGreat),
+    (">|", Operator::Clobber),
+    ("<", Operator::Less),
+    (">", Operator::Great),
+    ("|", Operator::Pipe),
+    (";", Operator::Semi),
+    ("&", Operator::Amp),
+    ("(", Operator::LParen),
+    (")", Operator::RParen),
+];
+
+impl Operator {
+    pub fn as_str(&self) -> &'static str {
+        OPERATORS.iter()
+            .find(|(_, op)| op == self)
+            .map(|(text, _)| *text)
+            .unwrap_or("")
+    }
+}
+
+fn is_operator_start(c: char) -> bool {
+    matches!(c, '&' | '|' | ';' | '<' | '>' | '(' | ')')
+}
+
+fn is_blank(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+fn unterminated(what: &str) -> ParseError {
+    ParseError::Incomplete(format!("Unterminated {}", what))
+}
+
+// Quote removal for here-document delimiters, which are never expanded
+fn delimiter_text(word: &str) -> String {
+    let mut text = String::with_capacity(word.len());
+    let mut quote_char: Option<char> = None;
+    let mut chars = word.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote_char, c) {
+            (None, '\\') => text.extend(chars.next()),
+            (None, '\'') | (None, '"') => quote_char = Some(c),
+            (Some(q), _) if q == c => quote_char = None,
+            _ => text.push(c),
+        }
+    }
+    text
+}
+
+// Length in characters of the `$` or backquote expansion at the start of
+// the text, as found by the tokenizer. Returns 1 if there is no expansion.
+pub fn expansion_length(text: &str) -> usize {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some('$'), Some('{')) | (Some('$'), Some('(')) | (Some('`'), _) => {
+            let mut lexer = Lexer::new(text);
+            let mut word = String::new();
+            match lexer.read_word_char(&mut word) {
+                Ok(()) => lexer.pos,
+                Err(_) => 1,
+            }
+        },
+        (Some('$'), Some(c)) if c.is_ascii_alphabetic() || c == '_' => {
+            1 + text[1..].chars()
+                .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+                .count()
+        },
+        (Some('$'), Some(c)) if c.is_ascii_digit() || "@*#?-$!".contains(c) => 2,
+        _ => 1,
+    }
+}
+
+// Token recognition as described in XCU 2.3: words keep their quoting
+// characters, which are only removed after expansion.
+pub struct Lexer {
+    input: Vec<char>,
+    pos: usize,
+    queue: VecDeque<Token>,
+}
+
+impl Lexer {
+    pub fn new(text: &str) -> Self {
+        Lexer { input: text.chars().collect(), pos: 0, queue: VecDeque::new() }
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.peek_char();
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
+    }
+
+    fn starts_with(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        self.input[self.pos..].starts_with(&chars)
+    }
+
+    fn skip_blanks_and_comments(&mut self) {
+        while let Some(c) = self.peek_char() {
+            if is_blank(c) {
+                self.pos += 1;
+            } else if self.starts_with("\\\n") {
+                self.pos += 2;
+            } else if c == '#' {
+                while !matches!(self.peek_char(), None | Some('\n')) {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    pub fn next_token(&mut self) -> Result<Token, ParseError> {
+        if let Some(token) = self.queue.pop_front() {
+            return Ok(token);
+        }
+
+        match self.scan_token()? {
+            Token::Operator(op @ (Operator::DLess | Operator::DLessDash)) => {
+                self.scan_here_doc_line(op)?;
+                self.next_token()
+            },
+            token => Ok(token),
+        }
+    }
+
+    // Here-document bodies start after the next newline, so the rest of the
+    // line is tokenized ahead and kept in the queue until the bodies are read
+    fn scan_here_doc_line(&mut self, first_op: Operator) -> Result<(), ParseError> {
+        let mut tokens = vec![Token::Operator(first_op)];
+        let mut pending = Vec::new();
+        let mut here_doc_op = Some(first_op);
+
+        loop {
+            let token = self.scan_token()?;
+            if let (Some(op), Token::Word(word)) = (here_doc_op.take(), &token) {
+                pending.push((tokens.len(), word.clone(), op == Operator::DLessDash));
+            }
+            match token {
+                Token::Operator(op @ (Operator::DLess | Operator::DLessDash)) => {
+                    here_doc_op = Some(op);
+                },
+                Token::Newline => { tokens.push(token); break; },
+                Token::Eof => return Err(unterminated("here-document")),
+                _ => {},
+            }
+            tokens.push(token);
+        }
+
+        for (index, word, strip_tabs) in pending {
+            let delimiter = delimiter_text(&word);
+            let quoted = word.contains(['\'', '"', '\\']);
+            let body = self.read_here_doc_body(&delimiter, strip_tabs)?;
+            tokens[index] = Token::HereDoc { body, quoted };
+        }
+
+        self.queue.extend(tokens);
+        Ok(())
+    }
+
+    fn read_here_doc_body(&mut self, delimiter: &str, strip_tabs: bool) -> Result<String, ParseError> {
+        let mut body = String::new();
+
+        loop {
+            if self.pos >= self.input.len() {
+                return Err(unterminated("here-document"));
+            }
+
+            let mut line = String::new();
+            while let Some(c) = self.next_char() {
+                if c == '\n' {
+                    break;
+                }
+                line.push(c);
+            }
+
+            let line = if strip_tabs { line.trim_start_matches('\t') } else { &line };
+            if line == delimiter {
+                return Ok(body);
+            }
+            body.push_str(line);
+            body.push('\n');
+        }
+    }
+
+    fn scan_token(&mut self) -> Result<Token, ParseError> {
+        self.skip_blanks_and_comments();
+
+        let Some(c) = self.peek_char() else {
+            return Ok(Token::Eof);
+        };
+
+        if c == '\n' {
+            self.pos += 1;
+            return Ok(Token::Newline);
+        }
+
+        if is_operator_start(c) {
+            for (text, op) in OPERATORS {
+                if self.starts_with(text) {
+                    self.pos += text.chars().count();
+                    return Ok(Token::Operator(op));
+                }
+            }
+        }
+
+        let word = self.read_word()?;
+        if word.chars().all(|c| c.is_ascii_digit()) &&
+            matches!(self.peek_char(), Some('<') | Some('>')) {
+            if let Ok(fd) = word.parse() {
+                return Ok(Token::IoNumber(fd));
+            }
+        }
+        Ok(Token::Word(word))
+    }
+
+    fn read_word(&mut self) -> Result<String, ParseError> {
+        let mut word = String::new();
+
+        while let Some(c) = self.peek_char() {
+            if is_blank(c) || c == '\n' || is_operator_start(c) {
+                break;
+            }
+            self.read_word_char(&mut word)?;
+        }
+
+        Ok(word)
+    }
+
+    // Consume one unit of a word: a plain character or a whole quoted or
+    // expansion construct, which may contain otherwise delimiting characters
+    fn read_word_char(&mut self, word: &mut String) -> Result<(), ParseError> {
+        let Some(c) = self.next_char() else { return Ok(()); };
+        word.push(c);
+
+        match c {
+            // A backslash before a newline joins the lines
+            '\\' => match self.next_char() {
+                Some('\n') => { word.pop(); },
+                Some(next) => word.push(next),
+                None => return Err(ParseError::Incomplete(String::from("Unexpected end of line"))),
+            },
+            '\'' => self.read_single_quotes(word)?,
+            '"' => self.read_double_quotes(word)?,
+            '`' => self.read_backquotes(word)?,
+            '$' => self.read_dollar(word)?,
+            _ => {},
+        }
+        Ok(())
+    }
+
+    fn read_single_quotes(&mut self, word: &mut String) -> Result<(), ParseError> {
+        loop {
+            match self.next_char() {
+                Some('\'') => { word.push('\''); return Ok(()); },
+                Some(c) => word.push(c),
+                None => return Err(unterminated("quoted string")),
+            }
+        }
+    }
+
+    fn read_double_quotes(&mut self, word: &mut String) -> Result<(), ParseError> {
+        loop {
+            match self.peek_char() {
+                Some('"') => {
+                    self.pos += 1;
+                    word.push('"');
+                    return Ok(());
+                },
+                Some('\\') if self.starts_with("\\\n") => self.pos += 2,
+                Some('\\') => {
+                    self.pos += 1;
+                    word.push('\\');
+                    if let Some(next) = self.next_char() {
+                        word.push(next);
+                    }
+                },
+                Some('`') | Some('$') => self.read_word_char(word)?,
+                Some(c) => { self.pos += 1; word.push(c); },
+                None => return Err(unterminated("quoted string")),
+            }
+        }
+    }
+
+    fn read_backquotes(&mut self, word: &mut String) -> Result<(), ParseError> {
+        loop {
+            match self.next_char() {
+                Some('`') => { word.push('`'); return Ok(()); },
+                Some('\\') => {
+                    word.push('\\');
+                    if let Some(next) = self.next_char() {
+                        word.push(next);
+                    }
+                },
+                Some(c) => word.push(c),
+                None => return Err(unterminated("backquote")),
+            }
+        }
+    }
+
+    fn read_dollar(&mut self, word: &mut String) -> Result<(), ParseError> {
+        if self.starts_with("((") {
+            self.read_arithmetic(word)
+        } else if self.starts_with("(") {
+            self.read_command_substitution(word)
+        } else if self.starts_with("{") {
+            self.read_braces(word)
+        } else if self.starts_with("'") {
+            self.pos += 1;
+            word.push('\'');
+            self.read_ansi_c_quotes(word)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn read_ansi_c_quotes(&mut self, word: &mut String) -> Result<(), ParseError> {
+        loop {
+            match self.next_char() {
+                Some('\'') => { word.push('\''); return Ok(()); },
+                Some('\\') => {
+                    word.push('\\');
+                    if let Some(next) = self.next_char() {
+                        word.push(next);
+                    }
+                },
+                Some(c) => word.push(c),
+                None => return Err(unterminated("quoted string")),
+            }
+        }
+    }
+
+    fn read_braces(&mut self, word: &mut String) -> Result<(), ParseError> {
+        self.pos += 1;
+        word.push('{');
+        loop {
+            match self.peek_char() {
+                Some('}') => {
+                    self.pos += 1;
+                    word.push('}');
+                    return Ok(());
+                },
+                Some(_) => self.read_word_char(word)?,
+                None => return Err(unterminated("parameter expansion")),
+            }
+        }
+    }
+
+    fn read_arithmetic(&mut self, word: &mut String) -> Result<(), ParseError> {
+        let mut depth = 0;
+        loop {
+            match self.peek_char() {
+                Some('(') => { depth += 1; self.pos += 1; word.push('('); },
+                Some(')') => {
+                    depth -= 1;
+                    self.pos += 1;
+                    word.push(')');
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                },
+                Some(_) => self.read_word_char(word)?,
+                None => return Err(unterminated("arithmetic expansion")),
+            }
+        }
+    }
+
+    // Find the closing parenthesis by parsing the enclosed command, so that
+    // quoted or commented parentheses and case patterns are not miscounted
+    fn read_command_substitution(&mut self, word: &mut String) -> Result<(), ParseError> {
+        let start = self.pos;
+        let lexer = Lexer {
+            input: std::mem::take(&mut self.input),
+            pos: start + 1,
+            queue: VecDeque::new(),
+        };
+        let mut parser = Parser { lexer, tokens: VecDeque::new() };
+
+        let result = parser.list(true).and_then(|_| parser.next());
+        self.input = std::mem::take(&mut parser.lexer.input);
+        self.pos = parser.lexer.pos;
+
+        match result {
+            Ok(Token::Operator(Operator::RParen)) => {
+                word.extend(&self.input[start..self.pos]);
+                Ok(())
+            },
+            Err(ParseError::Incomplete(_)) | Ok(Token::Eof) => {
+                Err(unterminated("command substitution"))
+            },
+            Ok(token) => Err(unexpected(&token, Some(")"))),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod next_token {
+    use super::{Lexer, Token, Operator};
+    use crate::vish::parser::ParseError;
+
+    fn tokenize(text: &str) -> Result<Vec<Token>, ParseError> {
+        let mut lexer = Lexer::new(text);
+        let mut tokens = Vec::new();
+        loop {
+            match lexer.next_token()? {
+                Token::Eof => return Ok(tokens),
+                token => tokens.push(token),
+            }
+        }
+    }
+
+    fn word(text: &str) -> Token {
+        Token::Word(String::from(text))
+    }
+
+    #[test]
+    fn split_words_on_blanks() {
+        assert_eq!(
+            tokenize("echo  hello\tworld").unwrap(),
+            vec![word("echo"), word("hello"), word("world")]
+        );
+    }
+
+    #[test]
+    fn recognize_operators() {
+        assert_eq!(
+            tokenize("a|b&&c||d;e&").unwrap(),
+            vec![
+                word("a"), Token::Operator(Operator::Pipe),
+                word("b"), Token::Operator(Operator::AndIf),
+                word("c"), Token::Operator(Operator::OrIf),
+                word("d"), Token::Operator(Operator::Semi),
+                word("e"), Token::Operator(Operator::Amp),
+            ]
+        );
+    }
+
+    #[test]
+    fn recognize_io_number() {
+        assert_eq!(
+            tokenize("cmd 2>&1 2 >out").unwrap(),
+            vec![
+                word("cmd"), Token::IoNumber(2),
+                Token::Operator(Operator::GreatAnd), word("1"),
+                word("2"), Token::Operator(Operator::Great), word("out"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_quoted_operators_in_word() {
+        assert_eq!(
+            tokenize("echo 'a | b' \"c; d\" e\\;f").unwrap(),
+            vec![word("echo"), word("'a | b'"), word("\"c; d\""), word("e\\;f")]
+        );
+    }
+
+    #[test]
+    fn join_continued_lines() {
+        assert_eq!(
+            tokenize("echo a\\\nb \\\n c \"d\\\ne\" 'f\\\ng'").unwrap(),
+            vec![word("echo"), word("ab"), word("c"), word("\"de\""), word("'f\\\ng'")]
+        );
+        assert!(matches!(tokenize("echo \\"), Err(ParseError::Incomplete(_))));
+    }
+
+    #[test]
+    fn join_adjacent_quoted_segments() {
+        assert_eq!(
+            tokenize("--name=\"John Smith\"").unwrap(),
+            vec![word("--name=\"John Smith\"")]
+        );
+    }
+
+    #[test]
+    fn keep_substitutions_in_word() {
+        assert_eq!(
+            tokenize("echo $(ls | wc -l) ${x:-a b} $((1 + (2))) `a b`").unwrap(),
+            vec![
+                word("echo"), word("$(ls | wc -l)"), word("${x:-a b}"),
+                word("$((1 + (2)))"), word("`a b`"),
+            ]
+        );
+    }
+
+    #[test]
+    fn skip_comments() {
+        assert_eq!(
+            tokenize("echo a#b # comment\nls").unwrap(),
+            vec![word("echo"), word("a#b"), Token::Newline, word("ls")]
+        );
+    }
+
+    #[test]
+    fn read_here_documents_after_newline() {
+        assert_eq!(
+            tokenize("cat <<EOF <<-'END'; ls\nhello $x\nEOF\n\tworld\n\tEND\npwd").unwrap(),
+            vec![
+                word("cat"), Token::Operator(Operator::DLess),
+                Token::HereDoc { body: "hello $x\n".into(), quoted: false },
+                Token::Operator(Operator::DLessDash),
+                Token::HereDoc { body: "world\n".into(), quoted: true },
+                Token::Operator(Operator::Semi), word("ls"), Token::Newline,
+                word("pwd"),
+            ]
+        );
+    }
+
+    #[test]
+    fn report_unterminated_here_document() {
+        assert!(matches!(tokenize("cat <<EOF"), Err(ParseError::Incomplete(_))));
+        assert!(matches!(tokenize("cat <<EOF\nabc"), Err(ParseError::Incomplete(_))));
+        assert!(tokenize("cat <<EOF\nabc\nEOF").is_ok());
+    }
+
+    #[test]
+    fn report_unterminated_quotes() {
+        assert!(matches!(tokenize("echo 'abc"), Err(ParseError::Incomplete(_))));
+        assert!(matches!(tokenize("echo \"abc"), Err(ParseError::Incomplete(_))));
+        assert!(matches!(tokenize("echo $(ls"), Err(ParseError::Incomplete(_))));
+        assert!(matches!(tokenize("echo $(ls;;)"), Err(ParseError::Syntax(_))));
+    }
+}
@@ -0,0 +1,639 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+
+mod lexer;
+pub use lexer::{Lexer, Token, Operator, expansion_length};
+
+pub mod ast;
+pub use ast::*;
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    // The input ended before the command was complete, more lines may fix it
+    Incomplete(String),
+    Syntax(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Incomplete(msg) => write!(f, "Syntax error: {}", msg),
+            ParseError::Syntax(msg) => write!(f, "Syntax error: {}", msg),
+        }
+    }
+}
+
+type ParseResult<T> = Result<T, ParseError>;
+
+// Reserved words that close a compound command and can't start a command
+const TERMINATORS: [&str; 8] = ["then", "else", "elif", "fi", "do", "done", "esac", "}"];
+
+const RESERVED_WORDS: [&str; 16] = [
+    "!", "{", "}", "case", "do", "done", "elif", "else", "esac", "fi", "for",
+    "if", "in", "then", "until", "while",
+];
+
+pub fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        },
+        _ => false,
+    }
+}
+
+pub fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => is_name(name),
+        None => false,
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Word(word) => format!("\"{}\"", word),
+        Token::IoNumber(fd) => format!("\"{}\"", fd),
+        Token::Operator(op) => format!("\"{}\"", op.as_str()),
+        Token::HereDoc { .. } => String::from("here-document"),
+        Token::Newline => String::from("newline"),
+        Token::Eof => String::from("end of file"),
+    }
+}
+
+fn unexpected(token: &Token, expecting: Option<&str>) -> ParseError {
+    let msg = match expecting {
+        Some(word) => format!("{} unexpected (expecting \"{}\")", describe(token), word),
+        None => format!("{} unexpected", describe(token)),
+    };
+    match token {
+        Token::Eof => ParseError::Incomplete(msg),
+        _ => ParseError::Syntax(msg),
+    }
+}
+
+// Recursive descent parser for the shell grammar in XCU 2.10
+pub struct Parser {
+    lexer: Lexer,
+    tokens: VecDeque<Token>,
+}
+
+impl Parser {
+    pub fn new(text: &str) -> Self {
+        Parser { lexer: Lexer::new(text), tokens: VecDeque::new() }
+    }
+
+    fn peek_nth(&mut self, n: usize) -> ParseResult<Token> {
+        while self.tokens.len() <= n {
+            let token = self.lexer.next_token()?;
+            self.tokens.push_back(token);
+        }
+        Ok(self.tokens[n].clone())
+    }
+
+    fn peek(&mut self) -> ParseResult<Token> {
+        self.peek_nth(0)
+    }
+
+    fn next(&mut self) -> ParseResult<Token> {
+        let token = self.peek()?;
+        self.tokens.pop_front();
+        Ok(token)
+    }
+
+    fn is_reserved(&mut self, word: &str) -> ParseResult<bool> {
+        Ok(matches!(self.peek()?, Token::Word(w) if w == word))
+    }
+
+    fn expect_reserved(&mut self, word: &str) -> ParseResult<()> {
+        if self.is_reserved(word)? {
+            self.next()?;
+            Ok(())
+        } else {
+            Err(unexpected(&self.peek()?, Some(word)))
+        }
+    }
+
+    fn expect_operator(&mut self, op: Operator) -> ParseResult<()> {
+        match self.peek()? {
+            Token::Operator(o) if o == op => { self.next()?; Ok(()) },
+            token => Err(unexpected(&token, Some(op.as_str()))),
+        }
+    }
+
+    fn linebreak(&mut self) -> ParseResult<()> {
+        while self.peek()? == Token::Newline {
+            self.next()?;
+        }
+        Ok(())
+    }
+
+    fn can_start_command(&mut self) -> ParseResult<bool> {
+        Ok(match self.peek()? {
+            Token::Word(word) => !TERMINATORS.contains(&word.as_str()),
+            Token::IoNumber(_) => true,
+            Token::Operator(op) => matches!(op,
+                Operator::LParen | Operator::Less | Operator::Great |
+                Operator::DGreat | Operator::Clobber | Operator::LessGreat |
+                Operator::LessAnd | Operator::GreatAnd | Operator::DLess |
+                Operator::DLessDash),
+            Token::HereDoc { .. } | Token::Newline | Token::Eof => false,
+        })
+    }
+
+    // Parse the next complete command, or return None at the end of input
+    pub fn complete_command(&mut self) -> ParseResult<Option<List>> {
+        self.linebreak()?;
+        if self.peek()? == Token::Eof {
+            return Ok(None);
+        }
+
+        let list = self.list(false)?;
+        match self.next()? {
+            Token::Newline | Token::Eof => Ok(Some(list)),
+            token => Err(unexpected(&token, None)),
+        }
+    }
+
+    // A compound list may span several lines and ends at a reserved word
+    // or operator that can't start a command
+    fn list(&mut self, compound: bool) -> ParseResult<List> {
+        let mut items = Vec::new();
+
+        if compound {
+            self.linebreak()?;
+            if !self.can_start_command()? {
+                return Ok(List { items });
+            }
+        }
+
+        loop {
+            let and_or = self.and_or()?;
+            let asynchronous = match self.peek()? {
+                Token::Operator(Operator::Amp) => { self.next()?; true },
+                Token::Operator(Operator::Semi) => { self.next()?; false },
+                Token::Newline if compound => false,
+                _ => {
+                    items.push(ListItem { and_or, asynchronous: false });
+                    break;
+                },
+            };
+            items.push(ListItem { and_or, asynchronous });
+
+            if compound {
+                self.linebreak()?;
+            }
+            if !self.can_start_command()? {
+                break;
+            }
+        }
+
+        Ok(List { items })
+    }
+
+    fn compound_list(&mut self, expecting: &str) -> ParseResult<List> {
+        let list = self.list(true)?;
+        if list.items.is_empty() {
+            Err(unexpected(&self.peek()?, Some(expecting)))
+        } else {
+            Ok(list)
+        }
+    }
+
+    fn and_or(&mut self) -> ParseResult<AndOr> {
+        let first = self.pipeline()?;
+        let mut rest = Vec::new();
+
+        loop {
+            let op = match self.peek()? {
+                Token::Operator(Operator::AndIf) => LogicalOp::And,
+                Token::Operator(Operator::OrIf) => LogicalOp::Or,
+                _ => break,
+            };
+            self.next()?;
+            self.linebreak()?;
+            rest.push((op, self.pipeline()?));
+        }
+
+        Ok(AndOr { first, rest })
+    }
+
+    fn pipeline(&mut self) -> ParseResult<Pipeline> {
+        let bang = self.is_reserved("!")?;
+        if bang {
+            self.next()?;
+        }
+
+        let mut commands = vec![self.command()?];
+        while self.peek()? == Token::Operator(Operator::Pipe) {
+            self.next()?;
+            self.linebreak()?;
+            commands.push(self.command()?);
+        }
+
+        Ok(Pipeline { bang, commands })
+    }
+
+    fn command(&mut self) -> ParseResult<Command> {
+        if let Some(compound) = self.compound_command()? {
+            let redirects = self.redirect_list()?;
+            return Ok(Command::Compound(compound, redirects));
+        }
+
+        if let Token::Word(word) = self.peek()? {
+            if TERMINATORS.contains(&word.as_str()) {
+                return Err(unexpected(&self.peek()?, None));
+            }
+            if is_name(&word) && !RESERVED_WORDS.contains(&word.as_str()) &&
+                self.peek_nth(1)? == Token::Operator(Operator::LParen) {
+                return self.function_definition();
+            }
+        }
+
+        let command = self.simple_command()?;
+        if command.words.is_empty() && command.assignments.is_empty() &&
+            command.redirects.is_empty() {
+            return Err(unexpected(&self.peek()?, None));
+        }
+        Ok(Command::Simple(command))
+    }
+
+    fn simple_command(&mut self) -> ParseResult<SimpleCommand> {
+        let mut command = SimpleCommand::default();
+
+        loop {
+            if let Some(redirect) = self.redirect()? {
+                command.redirects.push(redirect);
+                continue;
+            }
+            match self.peek()? {
+                Token::Word(word) => {
+                    self.next()?;
+                    if command.words.is_empty() && is_assignment(&word) {
+                        command.assignments.push(word);
+                    } else {
+                        command.words.push(word);
+                    }
+                },
+                _ => break,
+            }
+        }
+
+        Ok(command)
+    }
+
+    fn redirect(&mut self) -> ParseResult<Option<Redirect>> {
+        let (fd, index) = match self.peek()? {
+            Token::IoNumber(fd) => (Some(fd), 1),
+            _ => (None, 0),
+        };
+
+        let op = match self.peek_nth(index)? {
+            Token::Operator(Operator::Less) => RedirectOp::Input,
+            Token::Operator(Operator::Great) => RedirectOp::Output,
+            Token::Operator(Operator::DGreat) => RedirectOp::Append,
+            Token::Operator(Operator::Clobber) => RedirectOp::Clobber,
+            Token::Operator(Operator::LessGreat) => RedirectOp::ReadWrite,
+            Token::Operator(Operator::LessAnd) => RedirectOp::DupInput,
+            Token::Operator(Operator::GreatAnd) => RedirectOp::DupOutput,
+            Token::Operator(Operator::DLess | Operator::DLessDash) => {
+                RedirectOp::HereDoc { quoted: false }
+            },
+            _ => return Ok(None),
+        };
+        for _ in 0..=index {
+            self.next()?;
+        }
+
+        match (op, self.next()?) {
+            (RedirectOp::HereDoc { .. }, Token::HereDoc { body, quoted }) => {
+                let op = RedirectOp::HereDoc { quoted };
+                Ok(Some(Redirect { fd, op, target: body }))
+            },
+            (RedirectOp::HereDoc { .. }, token) => Err(unexpected(&token, None)),
+            (_, Token::Word(target)) => Ok(Some(Redirect { fd, op, target })),
+            (_, token) => Err(unexpected(&token, None)),
+        }
+    }
+
+    fn redirect_list(&mut self) -> ParseResult<Vec<Redirect>> {
+        let mut redirects = Vec::new();
+        while let Some(redirect) = self.redirect()? {
+            redirects.push(redirect);
+        }
+        Ok(redirects)
+    }
+
+    fn function_definition(&mut self) -> ParseResult<Command> {
+        let Token::Word(name) = self.next()? else { unreachable!() };
+        self.expect_operator(Operator::LParen)?;
+        self.expect_operator(Operator::RParen)?;
+        self.linebreak()?;
+
+        match self.compound_command()? {
+            Some(compound) => {
+                let redirects = self.redirect_list()?;
+                let body = Rc::new((compound, redirects));
+                Ok(Command::FunctionDefinition(FunctionDefinition { name, body }))
+            },
+            None => Err(unexpected(&self.peek()?, None)),
+        }
+    }
+
+    fn compound_command(&mut self) -> ParseResult<Option<CompoundCommand>> {
+        let compound = match self.peek()? {
+            Token::Operator(Operator::LParen) => self.subshell()?,
+            Token::Word(word) => match word.as_str() {
+                "{" => self.brace_group()?,
+                "if" => self.if_clause()?,
+                "while" => self.while_clause(false)?,
+                "until" => self.while_clause(true)?,
+                "for" => self.for_clause()?,
+                "case" => self.case_clause()?,
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(compound))
+    }
+
+    fn subshell(&mut self) -> ParseResult<CompoundCommand> {
+        self.next()?;
+        let list = self.compound_list(")")?;
+        self.expect_operator(Operator::RParen)?;
+        Ok(CompoundCommand::Subshell(list))
+    }
+
+    fn brace_group(&mut self) -> ParseResult<CompoundCommand> {
+        self.next()?;
+        let list = self.compound_list("}")?;
+        self.expect_reserved("}")?;
+        Ok(CompoundCommand::BraceGroup(list))
+    }
+
+    fn if_clause(&mut self) -> ParseResult<CompoundCommand> {
+        let mut conditionals = Vec::new();
+        let mut else_part = None;
+
+        self.next()?;
+        loop {
+            let condition = self.compound_list("then")?;
+            self.expect_reserved("then")?;
+            let body = self.compound_list("fi")?;
+            conditionals.push((condition, body));
+
+            if self.is_reserved("elif")? {
+                self.next()?;
+            } else {
+                break;
+            }
+        }
+
+        if self.is_reserved("else")? {
+            self.next()?;
+            else_part = Some(self.compound_list("fi")?);
+        }
+        self.expect_reserved("fi")?;
+
+        Ok(CompoundCommand::If { conditionals, else_part })
+    }
+
+    fn while_clause(&mut self, until: bool) -> ParseResult<CompoundCommand> {
+        self.next()?;
+        let condition = self.compound_list("do")?;
+        let body = self.do_group()?;
+        if until {
+            Ok(CompoundCommand::Until { condition, body })
+        } else {
+            Ok(CompoundCommand::While { condition, body })
+        }
+    }
+
+    fn do_group(&mut self) -> ParseResult<List> {
+        self.expect_reserved("do")?;
+        let body = self.compound_list("done")?;
+        self.expect_reserved("done")?;
+        Ok(body)
+    }
+
+    fn sequential_separator(&mut self) -> ParseResult<()> {
+        match self.peek()? {
+            Token::Operator(Operator::Semi) | Token::Newline => {
+                self.next()?;
+                self.linebreak()
+            },
+            token => Err(unexpected(&token, Some("do"))),
+        }
+    }
+
+    fn for_clause(&mut self) -> ParseResult<CompoundCommand> {
+        self.next()?;
+        let name = match self.next()? {
+            Token::Word(name) if is_name(&name) => name,
+            token => return Err(unexpected(&token, None)),
+        };
+
+        let mut words = None;
+        if self.peek()? == Token::Operator(Operator::Semi) {
+            self.sequential_separator()?;
+        } else {
+            self.linebreak()?;
+            if self.is_reserved("in")? {
+                self.next()?;
+                let mut list = Vec::new();
+                while let Token::Word(word) = self.peek()? {
+                    self.next()?;
+                    list.push(word);
+                }
+                self.sequential_separator()?;
+                words = Some(list);
+            }
+        }
+
+        let body = self.do_group()?;
+        Ok(CompoundCommand::For { name, words, body })
+    }
+
+    fn case_clause(&mut self) -> ParseResult<CompoundCommand> {
+        self.next()?;
+        let word = match self.next()? {
+            Token::Word(word) => word,
+            token => return Err(unexpected(&token, None)),
+        };
+        self.linebreak()?;
+        self.expect_reserved("in")?;
+        self.linebreak()?;
+
+        let mut items = Vec::new();
+        while !self.is_reserved("esac")? {
+            if self.peek()? == Token::Operator(Operator::LParen) {
+                self.next()?;
+            }
+
+            let mut patterns = Vec::new();
+            loop {
+                match self.next()? {
+                    Token::Word(pattern) => patterns.push(pattern),
+                    token => return Err(unexpected(&token, None)),
+                }
+                if self.peek()? == Token::Operator(Operator::Pipe) {
+                    self.next()?;
+                } else {
+                    break;
+                }
+            }
+            self.expect_operator(Operator::RParen)?;
+
+            let list = self.list(true)?;
+            let body = if list.items.is_empty() { None } else { Some(list) };
+            items.push(CaseItem { patterns, body });
+
+            match self.peek()? {
+                Token::Operator(Operator::DSemi) => {
+                    self.next()?;
+                    self.linebreak()?;
+                },
+                Token::Word(w) if w == "esac" => {},
+                token => return Err(unexpected(&token, Some("esac"))),
+            }
+        }
+        self.next()?;
+
+        Ok(CompoundCommand::Case { word, items })
+    }
+}
+
+// Parse the whole text, which must consist of complete commands
+pub fn parse(text: &str) -> Result<Vec<List>, ParseError> {
+    let mut parser = Parser::new(text);
+    let mut commands = Vec::new();
+    while let Some(list) = parser.complete_command()? {
+        commands.push(list);
+    }
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod parse {
+    use super::*;
+
+    fn simple(words: &[&str]) -> Command {
+        Command::Simple(SimpleCommand {
+            words: words.iter().map(|w| w.to_string()).collect(),
+            ..SimpleCommand::default()
+        })
+    }
+
+    fn pipeline(commands: Vec<Command>) -> Pipeline {
+        Pipeline { bang: false, commands }
+    }
+
+    fn single(list: &List) -> &Command {
+        &list.items[0].and_or.first.commands[0]
+    }
+
+    #[test]
+    fn parse_simple_command() {
+        let commands = parse("echo hello world").unwrap();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(single(&commands[0]), &simple(&["echo", "hello", "world"]));
+    }
+
+    #[test]
+    fn parse_pipeline_and_lists() {
+        let commands = parse("ls | grep foo && echo ok; sleep 1 &").unwrap();
+        let items = &commands[0].items;
+        assert_eq!(items.len(), 2);
+        assert!(!items[0].asynchronous);
+        assert!(items[1].asynchronous);
+        assert_eq!(items[0].and_or.first, pipeline(vec![
+            simple(&["ls"]), simple(&["grep", "foo"]),
+        ]));
+        assert_eq!(items[0].and_or.rest, vec![
+            (LogicalOp::And, pipeline(vec![simple(&["echo", "ok"])])),
+        ]);
+    }
+
+    #[test]
+    fn parse_assignments_and_redirects() {
+        let commands = parse("FOO=bar cmd a=b 2>&1 >out").unwrap();
+        let Command::Simple(command) = single(&commands[0]) else { panic!() };
+        assert_eq!(command.assignments, vec!["FOO=bar"]);
+        assert_eq!(command.words, vec!["cmd", "a=b"]);
+        assert_eq!(command.redirects, vec![
+            Redirect { fd: Some(2), op: RedirectOp::DupOutput, target: "1".into() },
+            Redirect { fd: None, op: RedirectOp::Output, target: "out".into() },
+        ]);
+    }
+
+    #[test]
+    fn parse_here_documents() {
+        let commands = parse("cat <<A 3<<'B'\n$x\nA\ny\nB\necho").unwrap();
+        let Command::Simple(command) = single(&commands[0]) else { panic!() };
+        assert_eq!(command.redirects, vec![
+            Redirect { fd: None, op: RedirectOp::HereDoc { quoted: false }, target: "$x\n".into() },
+            Redirect { fd: Some(3), op: RedirectOp::HereDoc { quoted: true }, target: "y\n".into() },
+        ]);
+        assert_eq!(single(&commands[1]), &simple(&["echo"]));
+    }
+
+    #[test]
+    fn parse_if_clause() {
+        let commands = parse("if true\nthen echo a\nelif false; then echo b; else echo c; fi").unwrap();
+        let Command::Compound(CompoundCommand::If { conditionals, else_part }, _) =
+            single(&commands[0]) else { panic!() };
+        assert_eq!(conditionals.len(), 2);
+        assert!(else_part.is_some());
+    }
+
+    #[test]
+    fn parse_loops() {
+        let commands = parse("for i in a b; do echo $i; done; while false; do :; done").unwrap();
+        let Command::Compound(CompoundCommand::For { name, words, .. }, _) =
+            single(&commands[0]) else { panic!() };
+        assert_eq!(name, "i");
+        assert_eq!(words, &Some(vec!["a".to_string(), "b".to_string()]));
+        assert!(matches!(
+            commands[0].items[1].and_or.first.commands[0],
+            Command::Compound(CompoundCommand::While { .. }, _)
+        ));
+    }
+
+    #[test]
+    fn parse_case_clause() {
+        let commands = parse("case $x in\n(a|b) echo ab;;\n*) echo other\nesac").unwrap();
+        let Command::Compound(CompoundCommand::Case { word, items }, _) =
+            single(&commands[0]) else { panic!() };
+        assert_eq!(word, "$x");
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].patterns, vec!["a", "b"]);
+        assert_eq!(items[1].patterns, vec!["*"]);
+    }
+
+    #[test]
+    fn parse_groups_and_functions() {
+        let commands = parse("f() { echo a; } > out\n(cd /tmp)").unwrap();
+        let Command::FunctionDefinition(function) = single(&commands[0]) else { panic!() };
+        assert_eq!(function.name, "f");
+        assert_eq!(function.body.1.len(), 1);
+        assert!(matches!(
+            single(&commands[1]),
+            Command::Compound(CompoundCommand::Subshell(_), _)
+        ));
+    }
+
+    #[test]
+    fn report_incomplete_commands() {
+        for text in ["echo a |", "true &&", "if true; then echo", "while true", "{ echo", "'a"] {
+            assert!(matches!(parse(text), Err(ParseError::Incomplete(_))), "{}", text);
+        }
+    }
+
+    #[test]
+    fn report_syntax_errors() {
+        for text in ["fi", "echo a; )", "| cat", "if; then fi", "for 1 in a; do :; done"] {
+            assert!(matches!(parse(text), Err(ParseError::Syntax(_))), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,304 @@
+// Shell pattern matching notation, shared by case statements, pathname
+// expansion and the parameter expansions that remove a prefix or a suffix.
+// A backslash in the pattern makes the next character match literally, which
+// is how quoted parts of a word are passed in (see escape).
+
+#[derive(Debug, Clone, PartialEq)]
+enum BracketItem {
+    Char(char),
+    Range(char, char),
+    Class(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Char(char),
+    AnyChar,
+    AnyString,
+    Bracket { negated: bool, items: Vec<BracketItem> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+}
+
+// Make every character of the text match literally
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '?' | '[' | ']' | '!' | '^' | '-') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Remove the backslashes that escape characters of the pattern
+pub fn unescape(pattern: &str) -> String {
+    let mut text = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+fn class_matches(name: &str, c: char) -> bool {
+    match name {
+        "alnum" => c.is_alphanumeric(),
+        "alpha" => c.is_alphabetic(),
+        "blank" => c == ' ' || c == '\t',
+        "cntrl" => c.is_control(),
+        "digit" => c.is_ascii_digit(),
+        "graph" => !c.is_control() && !c.is_whitespace(),
+        "lower" => c.is_lowercase(),
+        "print" => !c.is_control(),
+        "punct" => c.is_ascii_punctuation(),
+        "space" => c.is_whitespace(),
+        "upper" => c.is_uppercase(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+impl BracketItem {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            BracketItem::Char(item) => *item == c,
+            BracketItem::Range(start, end) => *start <= c && c <= *end,
+            BracketItem::Class(name) => class_matches(name, c),
+        }
+    }
+}
+
+// Read a bracket expression starting after the opening bracket, returning it
+// with the position after the closing one, or None if it isn't closed
+fn parse_bracket(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut i = start;
+    let negated = matches!(chars.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut items = Vec::new();
+    let first = i;
+    loop {
+        let c = *chars.get(i)?;
+        if c == ']' && i > first {
+            return Some((Token::Bracket { negated, items }, i + 1));
+        }
+
+        if c == '[' && chars.get(i + 1) == Some(&':') {
+            let rest: String = chars[i + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                let name = &rest[..end];
+                items.push(BracketItem::Class(String::from(name)));
+                i += name.chars().count() + 4;
+                continue;
+            }
+        }
+
+        let (low, next) = match c {
+            '\\' => (*chars.get(i + 1)?, i + 2),
+            _ => (c, i + 1),
+        };
+        i = next;
+
+        if chars.get(i) == Some(&'-') && chars.get(i + 1).is_some_and(|c| *c != ']') {
+            let (high, next) = match chars[i + 1] {
+                '\\' => (*chars.get(i + 2)?, i + 3),
+                c => (c, i + 2),
+            };
+            items.push(BracketItem::Range(low, high));
+            i = next;
+        } else {
+            items.push(BracketItem::Char(low));
+        }
+    }
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Self {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                '\\' if i + 1 < chars.len() => {
+                    tokens.push(Token::Char(chars[i + 1]));
+                    i += 2;
+                },
+                '*' => {
+                    if tokens.last() != Some(&Token::AnyString) {
+                        tokens.push(Token::AnyString);
+                    }
+                    i += 1;
+                },
+                '?' => {
+                    tokens.push(Token::AnyChar);
+                    i += 1;
+                },
+                '[' => match parse_bracket(&chars, i + 1) {
+                    Some((token, next)) => {
+                        tokens.push(token);
+                        i = next;
+                    },
+                    None => {
+                        tokens.push(Token::Char('['));
+                        i += 1;
+                    },
+                },
+                c => {
+                    tokens.push(Token::Char(c));
+                    i += 1;
+                },
+            }
+        }
+
+        Self { tokens }
+    }
+
+    // Whether the pattern can match anything other than its own text
+    pub fn has_wildcards(&self) -> bool {
+        self.tokens.iter().any(|token| !matches!(token, Token::Char(_)))
+    }
+
+    // Whether the pattern starts with a character that matches literally
+    pub fn starts_with(&self, c: char) -> bool {
+        self.tokens.first() == Some(&Token::Char(c))
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        self.matches_chars(&chars)
+    }
+
+    // Length in bytes of the shortest or longest prefix of the text matching
+    // the pattern
+    pub fn match_prefix(&self, text: &str, longest: bool) -> Option<usize> {
+        let mut ends: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+        ends.push(text.len());
+        if longest {
+            ends.reverse();
+        }
+        ends.into_iter().find(|end| self.matches(&text[..*end]))
+    }
+
+    // Position in bytes of the shortest or longest suffix of the text
+    // matching the pattern
+    pub fn match_suffix(&self, text: &str, longest: bool) -> Option<usize> {
+        let mut starts: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+        starts.push(text.len());
+        if !longest {
+            starts.reverse();
+        }
+        starts.into_iter().find(|start| self.matches(&text[*start..]))
+    }
+
+    // Match with backtracking to the most recent star only, which is enough
+    // because a star can always absorb what a later star would have taken
+    fn matches_chars(&self, text: &[char]) -> bool {
+        let tokens = &self.tokens;
+        let (mut t, mut p) = (0, 0);
+        let mut backtrack: Option<(usize, usize)> = None;
+
+        while t < text.len() {
+            let token_matches = match tokens.get(p) {
+                Some(Token::Char(c)) => *c == text[t],
+                Some(Token::AnyChar) => true,
+                Some(Token::Bracket { negated, items }) => {
+                    items.iter().any(|item| item.matches(text[t])) != *negated
+                },
+                Some(Token::AnyString) => {
+                    backtrack = Some((p, t));
+                    p += 1;
+                    continue;
+                },
+                None => false,
+            };
+
+            if token_matches {
+                t += 1;
+                p += 1;
+            } else if let Some((star, start)) = backtrack {
+                backtrack = Some((star, start + 1));
+                p = star + 1;
+                t = start + 1;
+            } else {
+                return false;
+            }
+        }
+
+        tokens[p..].iter().all(|token| *token == Token::AnyString)
+    }
+}
+
+#[cfg(test)]
+mod matches {
+    use super::{Pattern, escape, unescape};
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Pattern::new(pattern).matches(text)
+    }
+
+    #[test]
+    fn match_literal_text() {
+        assert!(matches("hello", "hello"));
+        assert!(!matches("hello", "hell"));
+        assert!(matches("", ""));
+    }
+
+    #[test]
+    fn match_wildcards() {
+        assert!(matches("*", ""));
+        assert!(matches("*.rs", "main.rs"));
+        assert!(!matches("*.rs", "main.rc"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(matches("?at", "cat"));
+        assert!(!matches("?at", "at"));
+    }
+
+    #[test]
+    fn match_bracket_expressions() {
+        assert!(matches("[abc]", "b"));
+        assert!(matches("[a-z]x", "qx"));
+        assert!(!matches("[!a-z]", "q"));
+        assert!(matches("[^0-9]", "q"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches("[[:digit:]][[:upper:]]", "7Q"));
+        assert!(!matches("[[:alpha:]]", "1"));
+    }
+
+    #[test]
+    fn treat_unclosed_bracket_literally() {
+        assert!(matches("[ab", "[ab"));
+        assert!(!matches("[ab", "a"));
+    }
+
+    #[test]
+    fn match_escaped_characters_literally() {
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches(&escape("[a]*?"), "[a]*?"));
+        assert!(!matches(&escape("a*"), "abc"));
+        assert_eq!(unescape(&escape("[a]*?\\")), "[a]*?\\");
+    }
+
+    #[test]
+    fn find_prefixes_and_suffixes() {
+        let pattern = Pattern::new("*/");
+        assert_eq!(pattern.match_prefix("a/b/c", false), Some(2));
+        assert_eq!(pattern.match_prefix("a/b/c", true), Some(4));
+        let pattern = Pattern::new(".*");
+        assert_eq!(pattern.match_suffix("a.tar.gz", false), Some(5));
+        assert_eq!(pattern.match_suffix("a.tar.gz", true), Some(1));
+        assert_eq!(pattern.match_suffix("abc", true), None);
+    }
+}
@@ -0,0 +1,318 @@
+use crate::vish::environment::ShellEnvironment as Env;
+
+// Longest operators come first so that matching is greedy
+const OPERATORS: [&str; 34] = [
+    "<<=", ">>=", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "*=", "/=",
+    "%=", "+=", "-=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">",
+    "&", "^", "|", "!", "~", "?", ":", "=", "(",
+];
+
+const ASSIGNMENT_OPERATORS: [&str; 11] = [
+    "=", "*=", "/=", "%=", "+=", "-=", "<<=", ">>=", "&=", "^=", "|=",
+];
+
+// Binary operators from the lowest to the highest precedence
+const BINARY_OPERATORS: [&[&str]; 10] = [
+    &["||"], &["&&"], &["|"], &["^"], &["&"], &["==", "!="],
+    &["<", "<=", ">", ">="], &["<<", ">>"], &["+", "-"], &["*", "/", "%"],
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+    RParen,
+}
+
+// Integer constants are decimal, octal with a leading 0, or hexadecimal
+// with a leading 0x
+fn parse_number(text: &str) -> Option<i64> {
+    let (digits, radix) = if let Some(hex) = text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        (hex, 16)
+    } else if text.len() > 1 && text.starts_with('0') {
+        (&text[1..], 8)
+    } else {
+        (text, 10)
+    };
+    u64::from_str_radix(digits, radix).ok().map(|value| value as i64)
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            if c.is_ascii_digit() {
+                let number = parse_number(&text)
+                    .ok_or_else(|| format!("invalid number \"{}\"", text))?;
+                tokens.push(Token::Number(number));
+            } else {
+                tokens.push(Token::Name(text));
+            }
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            i += 1;
+        } else {
+            let rest: String = chars[i..].iter().take(3).collect();
+            let op = OPERATORS.iter()
+                .find(|op| rest.starts_with(**op))
+                .ok_or_else(|| format!("unexpected character \"{}\"", c))?;
+            tokens.push(Token::Operator(op));
+            i += op.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn apply_binary(op: &str, left: i64, right: i64) -> Result<i64, String> {
+    Ok(match op {
+        "*" => left.wrapping_mul(right),
+        "/" | "%" if right == 0 => return Err(String::from("division by zero")),
+        "/" => left.wrapping_div(right),
+        "%" => left.wrapping_rem(right),
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "<" => (left < right) as i64,
+        "<=" => (left <= right) as i64,
+        ">" => (left > right) as i64,
+        ">=" => (left >= right) as i64,
+        "==" => (left == right) as i64,
+        "!=" => (left != right) as i64,
+        "&" => left & right,
+        "^" => left ^ right,
+        "|" => left | right,
+        "&&" => (left != 0 && right != 0) as i64,
+        "||" => (left != 0 || right != 0) as i64,
+        _ => return Err(format!("unknown operator \"{}\"", op)),
+    })
+}
+
+// Recursive descent evaluator following the precedence of C. Operands that
+// are skipped by &&, || or ?: are still parsed, but without side effects.
+struct Evaluator<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    env: &'a mut Env,
+}
+
+impl Evaluator<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next_operator(&mut self, ops: &[&str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Operator(op)) if ops.contains(op) => {
+                let op = *op;
+                self.pos += 1;
+                Some(op)
+            },
+            _ => None,
+        }
+    }
+
+    fn expect_operator(&mut self, op: &str) -> Result<(), String> {
+        match self.next_operator(&[op]) {
+            Some(_) => Ok(()),
+            None => Err(format!("expecting \"{}\"", op)),
+        }
+    }
+
+    fn variable(&self, name: &str) -> Result<i64, String> {
+        let value = self.env.get_var(name).unwrap_or_default();
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(0);
+        }
+        let (negative, digits) = match value.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, value.strip_prefix('+').unwrap_or(value)),
+        };
+        match parse_number(digits) {
+            Some(number) if negative => Ok(number.wrapping_neg()),
+            Some(number) => Ok(number),
+            None => Err(format!("Illegal number: {}", value)),
+        }
+    }
+
+    fn assignment(&mut self, evaluate: bool) -> Result<i64, String> {
+        if let (Some(Token::Name(name)), Some(Token::Operator(op))) =
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+            if ASSIGNMENT_OPERATORS.contains(op) {
+                let (name, op) = (name.clone(), *op);
+                self.pos += 2;
+                let value = self.assignment(evaluate)?;
+                if !evaluate {
+                    return Ok(0);
+                }
+                let value = match op.strip_suffix('=') {
+                    Some("") => value,
+                    Some(binary_op) => apply_binary(binary_op, self.variable(&name)?, value)?,
+                    None => value,
+                };
+                self.env.set_var(&name, value.to_string())?;
+                return Ok(value);
+            }
+        }
+        self.conditional(evaluate)
+    }
+
+    fn conditional(&mut self, evaluate: bool) -> Result<i64, String> {
+        let condition = self.binary(0, evaluate)?;
+        if self.next_operator(&["?"]).is_none() {
+            return Ok(condition);
+        }
+        let when_true = self.assignment(evaluate && condition != 0)?;
+        self.expect_operator(":")?;
+        let when_false = self.conditional(evaluate && condition == 0)?;
+        Ok(if condition != 0 { when_true } else { when_false })
+    }
+
+    fn binary(&mut self, level: usize, evaluate: bool) -> Result<i64, String> {
+        if level == BINARY_OPERATORS.len() {
+            return self.unary(evaluate);
+        }
+
+        let mut left = self.binary(level + 1, evaluate)?;
+        while let Some(op) = self.next_operator(BINARY_OPERATORS[level]) {
+            let evaluate_right = match op {
+                "&&" => evaluate && left != 0,
+                "||" => evaluate && left == 0,
+                _ => evaluate,
+            };
+            let right = self.binary(level + 1, evaluate_right)?;
+            left = if evaluate_right {
+                apply_binary(op, left, right)?
+            } else if op == "&&" || op == "||" {
+                (left != 0) as i64
+            } else {
+                0
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self, evaluate: bool) -> Result<i64, String> {
+        match self.next_operator(&["+", "-", "!", "~"]) {
+            Some("+") => self.unary(evaluate),
+            Some("-") => Ok(self.unary(evaluate)?.wrapping_neg()),
+            Some("!") => Ok((self.unary(evaluate)? == 0) as i64),
+            Some(_) => Ok(!self.unary(evaluate)?),
+            None => self.primary(evaluate),
+        }
+    }
+
+    fn primary(&mut self, evaluate: bool) -> Result<i64, String> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Number(number)) => Ok(number),
+            Some(Token::Name(name)) if evaluate => self.variable(&name),
+            Some(Token::Name(_)) => Ok(0),
+            Some(Token::Operator("(")) => {
+                let value = self.assignment(evaluate)?;
+                match self.peek() {
+                    Some(Token::RParen) => { self.pos += 1; Ok(value) },
+                    _ => Err(String::from("expecting \")\"")),
+                }
+            },
+            _ => Err(String::from("expecting primary")),
+        }
+    }
+}
+
+// Evaluate an arithmetic expression whose parameters were already expanded
+pub fn evaluate_arithmetic(expression: &str, env: &mut Env) -> Result<i64, String> {
+    let tokens = tokenize(expression)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+
+    let mut evaluator = Evaluator { tokens, pos: 0, env };
+    let value = evaluator.assignment(true)?;
+    match evaluator.peek() {
+        None => Ok(value),
+        Some(_) => Err(String::from("syntax error")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::evaluate_arithmetic;
+    use crate::vish::environment::ShellEnvironment as Env;
+
+    #[test]
+    fn follow_operator_precedence() {
+        let mut env = Env::new();
+        assert_eq!(evaluate_arithmetic("1 + 2 * 3", &mut env), Ok(7));
+        assert_eq!(evaluate_arithmetic("(1 + 2) * 3", &mut env), Ok(9));
+        assert_eq!(evaluate_arithmetic("10 - 4 - 3", &mut env), Ok(3));
+        assert_eq!(evaluate_arithmetic("1 << 2 + 1", &mut env), Ok(8));
+        assert_eq!(evaluate_arithmetic("1 | 2 ^ 3 & 4", &mut env), Ok(3));
+        assert_eq!(evaluate_arithmetic("-2 * -3 + !0 + ~0", &mut env), Ok(6));
+        assert_eq!(evaluate_arithmetic("7 % 3 == 1 && 2 > 1", &mut env), Ok(1));
+        assert_eq!(evaluate_arithmetic("", &mut env), Ok(0));
+    }
+
+    #[test]
+    fn read_integer_constants() {
+        let mut env = Env::new();
+        assert_eq!(evaluate_arithmetic("0x1F + 010 + 9", &mut env), Ok(48));
+        assert!(evaluate_arithmetic("09", &mut env).is_err());
+        assert_eq!(evaluate_arithmetic("-9223372036854775807 - 1", &mut env), Ok(i64::MIN));
+    }
+
+    #[test]
+    fn evaluate_conditionals() {
+        let mut env = Env::new();
+        assert_eq!(evaluate_arithmetic("1 ? 2 : 3", &mut env), Ok(2));
+        assert_eq!(evaluate_arithmetic("0 ? 2 : 0 ? 3 : 4", &mut env), Ok(4));
+    }
+
+    #[test]
+    fn assign_variables() {
+        let mut env = Env::new();
+        env.set_var("x", String::from("5")).unwrap();
+        assert_eq!(evaluate_arithmetic("x += 2", &mut env), Ok(7));
+        assert_eq!(evaluate_arithmetic("y = x * 2", &mut env), Ok(14));
+        assert_eq!(evaluate_arithmetic("x <<= 1", &mut env), Ok(14));
+        assert_eq!(env.get_var("x"), Some(String::from("14")));
+        assert_eq!(env.get_var("y"), Some(String::from("14")));
+        assert_eq!(evaluate_arithmetic("unset_variable + 1", &mut env), Ok(1));
+    }
+
+    #[test]
+    fn skip_side_effects_of_unevaluated_operands() {
+        let mut env = Env::new();
+        assert_eq!(evaluate_arithmetic("0 && (x = 1)", &mut env), Ok(0));
+        assert_eq!(evaluate_arithmetic("1 || (x = 1)", &mut env), Ok(1));
+        assert_eq!(evaluate_arithmetic("1 ? 2 : (x = 1 / 0)", &mut env), Ok(2));
+        assert_eq!(env.get_var("x"), None);
+    }
+
+    #[test]
+    fn report_errors() {
+        let mut env = Env::new();
+        let division_by_zero = Err(String::from("division by zero"));
+        assert_eq!(evaluate_arithmetic("1 / 0", &mut env), division_by_zero);
+        assert_eq!(evaluate_arithmetic("1 % (2 - 2)", &mut env), division_by_zero);
+        assert!(evaluate_arithmetic("1 +", &mut env).is_err());
+        assert!(evaluate_arithmetic("(1", &mut env).is_err());
+        assert!(evaluate_arithmetic("1 2", &mut env).is_err());
+        assert!(evaluate_arithmetic("2 = 3", &mut env).is_err());
+    }
+}
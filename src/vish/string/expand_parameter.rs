@@ -1,74 +1,207 @@
-#[cfg(not(test))]
-use std::env::var as get_var;
+use crate::vish::environment::ShellEnvironment as Env;
+use crate::vish::parser::{expansion_length, is_name};
+use crate::vish::pattern::Pattern;
+use super::{expand_word, expand_quoted_word, expand_pattern};
 
-pub fn expand_parameter(input: String) -> String {
-    let dollar_index = input.find('$');
-    if dollar_index.is_none() {
-        return input;
-    }
+// Operators that may follow the parameter inside braces, longest first
+const OPERATORS: [&str; 12] = [":-", ":=", ":?", ":+", "%%", "##", "-", "=", "?", "+", "%", "#"];
+
+fn is_special(name: &str) -> bool {
+    matches!(name, "@" | "*" | "#" | "?" | "-" | "$" | "!")
+}
 
-    let mut i = dollar_index.unwrap();
-    let mut result = input.clone();
-    let is_valid = |c: char| c.is_alphanumeric() || c == '_';
+// Split the parameter from the start of the text inside braces. A name is as
+// long as possible, but a positional parameter or special one can't be.
+fn split_parameter(text: &str) -> (&str, &str) {
+    let len = if text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        text.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(text.len())
+    } else if text.starts_with(|c: char| c.is_ascii_digit()) {
+        text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len())
+    } else if text.get(..1).is_some_and(is_special) {
+        1
+    } else {
+        0
+    };
+    text.split_at(len)
+}
+
+// The positional parameters as a single string, which "$*" separates with
+// the first character of IFS
+pub fn join_parameters(separator: &str, env: &Env) -> Option<String> {
+    if env.positional_parameters.is_empty() {
+        None
+    } else {
+        Some(env.positional_parameters.join(separator))
+    }
+}
 
-    macro_rules! found_braces {
-        ($i:expr) => {
-            $i + 1 < result.len() && &result[$i + 1..=$i + 1] == "{"
+pub fn parameter_value(name: &str, env: &Env) -> Option<String> {
+    if let Ok(position) = name.parse::<usize>() {
+        if position == 0 {
+            return Some(env.shell_name.clone());
         }
+        return env.positional_parameters.get(position - 1).cloned();
+    }
+    match name {
+        "?" => Some(env.last_status.to_string()),
+        "$" => Some(env.shell_pid.to_string()),
+        "!" => env.last_background_pid.map(|pid| pid.to_string()),
+        "#" => Some(env.positional_parameters.len().to_string()),
+        "-" => Some(env.option_flags()),
+        "@" => join_parameters(" ", env),
+        "*" => {
+            let ifs = env.get_var("IFS").unwrap_or_else(|| String::from(" "));
+            join_parameters(&ifs.chars().take(1).collect::<String>(), env)
+        },
+        name => env.get_var(name),
+    }
+}
+
+fn fail(msg: String, env: &mut Env) -> String {
+    eprintln!("vish: {}", msg);
+    env.expansion_failed = true;
+    String::with_capacity(0)
+}
+
+// Result of an expansion in braces. The word after an operator is left to
+// the caller, which expands it with its quotes.
+pub enum Braces<'a> {
+    Value(String),
+    Word(&'a str),
+}
+
+// Expand what is written between the braces of ${...}, taking the word
+// after the operator as double-quoted when the whole expansion is
+pub fn expand_braces<'a>(text: &'a str, quoted: bool, env: &mut Env) -> Braces<'a> {
+    let expand = if quoted { expand_quoted_word } else { expand_word };
+
+    if let Some(name) = text.strip_prefix('#').filter(|name| !name.is_empty()) {
+        return match split_parameter(name) {
+            (name, "") if !name.is_empty() => {
+                let value = parameter_value(name, env).unwrap_or_default();
+                Braces::Value(value.chars().count().to_string())
+            },
+            _ => Braces::Value(fail(format!("${{{}}}: bad substitution", text), env)),
+        };
     }
 
-    while i < result.len() {
-        if &result[i..=i] != "$" {
-            i += 1;
-            continue;
-        } else if ! found_braces!(i) {
-            let mut end = i + 1;
-            while end < result.len() && result[end..=end].chars().all(is_valid) {
-                end += 1;
+    let (name, rest) = split_parameter(text);
+    let value = parameter_value(name, env);
+    if rest.is_empty() && !name.is_empty() {
+        return Braces::Value(value.unwrap_or_default());
+    }
+
+    let op = OPERATORS.iter().find(|op| rest.starts_with(**op));
+    let Some(op) = op.filter(|_| !name.is_empty()) else {
+        return Braces::Value(fail(format!("${{{}}}: bad substitution", text), env));
+    };
+    let word = &rest[op.len()..];
+
+    // With a colon, a null value is treated like an unset parameter
+    let is_set = match value.as_deref() {
+        Some("") => !op.starts_with(':'),
+        Some(_) => true,
+        None => false,
+    };
+
+    let value = match op.trim_start_matches(':') {
+        "-" if is_set => value.unwrap_or_default(),
+        "-" => return Braces::Word(word),
+        "=" if is_set => value.unwrap_or_default(),
+        "=" if !is_name(name) => {
+            let msg = format!("${}: cannot assign in this way", name);
+            return Braces::Value(fail(msg, env));
+        },
+        "=" => {
+            let value = expand(word, env);
+            match env.set_var(name, value.clone()) {
+                Ok(()) => value,
+                Err(msg) => return Braces::Value(fail(msg, env)),
             }
-            if end <= i + 1 {
-                i += 1;
-                continue;
+        },
+        "?" if is_set => value.unwrap_or_default(),
+        "?" => {
+            let msg = match expand(word, env) {
+                msg if msg.is_empty() && value.is_none() => String::from("parameter not set"),
+                msg if msg.is_empty() => String::from("parameter null or not set"),
+                msg => msg,
+            };
+            return Braces::Value(fail(format!("{}: {}", name, msg), env));
+        },
+        "+" if is_set => return Braces::Word(word),
+        "+" => String::with_capacity(0),
+        op => {
+            let value = value.unwrap_or_default();
+            let pattern = Pattern::new(&expand_pattern(word, env));
+            let longest = op.len() == 2;
+            if op.starts_with('#') {
+                match pattern.match_prefix(&value, longest) {
+                    Some(end) => String::from(&value[end..]),
+                    None => value,
+                }
+            } else {
+                match pattern.match_suffix(&value, longest) {
+                    Some(start) => String::from(&value[..start]),
+                    None => value,
+                }
+            }
+        },
+    };
+    Braces::Value(value)
+}
+
+// Expand every parameter in the text, either written as $name or ${...}
+pub fn expand_parameter(input: String, env: &mut Env) -> String {
+    let mut result = String::with_capacity(input.len());
+    let mut rest = input.as_str();
+
+    while let Some(index) = rest.find('$') {
+        result.push_str(&rest[..index]);
+        rest = &rest[index..];
+
+        let size = expansion_length(rest);
+        let end = rest.char_indices().nth(size).map_or(rest.len(), |(i, _)| i);
+        let text = &rest[..end];
+
+        if text.starts_with("${") && text.ends_with('}') {
+            match expand_braces(&text[2..text.len() - 1], true, env) {
+                Braces::Value(value) => result.push_str(&value),
+                Braces::Word(word) => result.push_str(&expand_quoted_word(word, env)),
             }
-            let name = &result[i + 1..end];
-            let value = get_var(name).unwrap_or_else(|_| String::new());
-            result.replace_range(i..end, &value);
-            i += value.len();
-        } else if let Some(end) = result[i + 2..].find('}') {
-            let start = i + 2;
-            let end = i + 2 + end;
-            let name = &result[start..end];
-            let value = get_var(name).unwrap_or_else(|_| String::new());
-            result.replace_range(i..end + 1, &value);
-            i += value.len();
+        } else if size > 1 && !text.starts_with("$(") && !text.starts_with("$'") {
+            result.push_str(&parameter_value(&text[1..], env).unwrap_or_default());
+        } else {
+            result.push_str(text);
         }
+        rest = &rest[end..];
     }
 
+    result.push_str(rest);
     result
 }
 
-#[cfg(test)]
-use std::collections::HashMap;
-
-#[cfg(test)]
-fn get_var(name: &str) -> Result<String, std::env::VarError> {
-    let mut table = HashMap::new();
-    let path = "/bin:/sbin:/usr/bin:/usr/sbin:/usr/local/bin:/usr/local/sbin";
-    table.insert("HOME", String::from("/home/bob"));
-    table.insert("PATH", String::from(path));
-    table.insert("PAT", String::from("Patricia"));
-    table.insert("PATH1", String::from("/bin"));
-    Ok(table.get(name).unwrap().clone())
-}
-
 #[cfg(test)]
 mod test {
     use super::expand_parameter;
+    use crate::vish::environment::ShellEnvironment as Env;
+
+    fn env() -> Env {
+        let mut env = Env::new();
+        let path = "/bin:/sbin:/usr/bin:/usr/sbin:/usr/local/bin:/usr/local/sbin";
+        env.set_var("HOME", String::from("/home/bob")).unwrap();
+        env.set_var("PATH", String::from(path)).unwrap();
+        env.set_var("PAT", String::from("Patricia")).unwrap();
+        env.set_var("PATH1", String::from("/bin")).unwrap();
+        env.set_var("FILE", String::from("archive.tar.gz")).unwrap();
+        env.set_var("EMPTY", String::new()).unwrap();
+        env.positional_parameters = vec![String::from("first")];
+        env
+    }
 
     #[test]
     fn expand_home_variable() {
         assert_eq!(
-            expand_parameter(String::from("${HOME}/.config")),
+            expand_parameter(String::from("${HOME}/.config"), &mut env()),
             "/home/bob/.config"
         );
     }
@@ -76,7 +209,7 @@ mod test {
     #[test]
     fn expand_home_variable_without_braces() {
         assert_eq!(
-            expand_parameter(String::from("$HOME/.config")),
+            expand_parameter(String::from("$HOME/.config"), &mut env()),
             "/home/bob/.config"
         );
     }
@@ -84,7 +217,7 @@ mod test {
     #[test]
     fn expand_home_variable_twice() {
         assert_eq!(
-            expand_parameter(String::from("${HOME} ${HOME}")),
+            expand_parameter(String::from("${HOME} ${HOME}"), &mut env()),
             "/home/bob /home/bob"
         );
     }
@@ -92,7 +225,7 @@ mod test {
     #[test]
     fn expand_wrong_variable() {
         assert_eq!(
-            expand_parameter(String::from("$PATH1")),
+            expand_parameter(String::from("$PATH1"), &mut env()),
             "/bin"
         );
     }
@@ -100,8 +233,63 @@ mod test {
     #[test]
     fn do_nothing() {
         assert_eq!(
-            expand_parameter(String::from("/bin")),
+            expand_parameter(String::from("/bin"), &mut env()),
             "/bin"
         );
     }
+
+    #[test]
+    fn use_default_values() {
+        let mut env = env();
+        assert_eq!(expand_parameter(String::from("${UNSET:-default}"), &mut env), "default");
+        assert_eq!(expand_parameter(String::from("${EMPTY:-default}"), &mut env), "default");
+        assert_eq!(expand_parameter(String::from("${EMPTY-default}"), &mut env), "");
+        assert_eq!(expand_parameter(String::from("${PAT:-default}"), &mut env), "Patricia");
+        assert_eq!(expand_parameter(String::from("${UNSET:-\"a } b\"}"), &mut env), "a } b");
+        assert_eq!(expand_parameter(String::from("${UNSET:-${PAT}}"), &mut env), "Patricia");
+        assert_eq!(expand_parameter(String::from("${1:-none} ${2:-none}"), &mut env), "first none");
+    }
+
+    #[test]
+    fn assign_default_values() {
+        let mut env = env();
+        assert_eq!(expand_parameter(String::from("${NEW:=value}"), &mut env), "value");
+        assert_eq!(env.get_var("NEW"), Some(String::from("value")));
+        assert_eq!(expand_parameter(String::from("${NEW:=other}"), &mut env), "value");
+    }
+
+    #[test]
+    fn use_alternative_values() {
+        let mut env = env();
+        assert_eq!(expand_parameter(String::from("${PAT:+set}"), &mut env), "set");
+        assert_eq!(expand_parameter(String::from("${EMPTY:+set}"), &mut env), "");
+        assert_eq!(expand_parameter(String::from("${EMPTY+set}"), &mut env), "set");
+        assert_eq!(expand_parameter(String::from("${UNSET+set}"), &mut env), "");
+    }
+
+    #[test]
+    fn report_unset_parameters() {
+        let mut env = env();
+        assert_eq!(expand_parameter(String::from("${PAT:?missing}"), &mut env), "Patricia");
+        assert!(!env.expansion_failed);
+        assert_eq!(expand_parameter(String::from("${UNSET:?missing}"), &mut env), "");
+        assert!(env.expansion_failed);
+    }
+
+    #[test]
+    fn count_characters() {
+        let mut env = env();
+        assert_eq!(expand_parameter(String::from("${#PAT} ${#UNSET} ${#1}"), &mut env), "8 0 5");
+    }
+
+    #[test]
+    fn remove_prefixes_and_suffixes() {
+        let mut env = env();
+        assert_eq!(expand_parameter(String::from("${FILE%.*}"), &mut env), "archive.tar");
+        assert_eq!(expand_parameter(String::from("${FILE%%.*}"), &mut env), "archive");
+        assert_eq!(expand_parameter(String::from("${FILE#*.}"), &mut env), "tar.gz");
+        assert_eq!(expand_parameter(String::from("${FILE##*.}"), &mut env), "gz");
+        assert_eq!(expand_parameter(String::from("${FILE#\"*\"}"), &mut env), "archive.tar.gz");
+        assert_eq!(expand_parameter(String::from("${PATH1#/}"), &mut env), "bin");
+    }
 }
@@ -0,0 +1,112 @@
+use std::fs;
+
+use crate::vish::pattern::{Pattern, unescape};
+
+// Names in the directory matching the pattern. A leading dot is only matched
+// by a pattern that starts with a dot itself.
+fn matching_names(dir: &str, pattern: &Pattern) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.') || pattern.starts_with('.'))
+        .filter(|name| pattern.matches(name))
+        .collect()
+}
+
+fn join(prefix: &str, name: &str, first: bool) -> String {
+    if first {
+        String::from(name)
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+// Follow the components of the pattern from the paths found so far
+fn expand_components(prefix: &str, components: &[&str], first: bool, paths: &mut Vec<String>) {
+    let Some((component, rest)) = components.split_first() else {
+        if fs::symlink_metadata(prefix).is_ok() {
+            paths.push(String::from(prefix));
+        }
+        return;
+    };
+
+    let pattern = Pattern::new(component);
+    if !pattern.has_wildcards() {
+        let path = join(prefix, &unescape(component), first);
+        expand_components(&path, rest, false, paths);
+        return;
+    }
+
+    let dir = match (first, prefix) {
+        (true, _) => ".",
+        (false, "") => "/",
+        (false, prefix) => prefix,
+    };
+    for name in matching_names(dir, &pattern) {
+        expand_components(&join(prefix, &name, first), rest, false, paths);
+    }
+}
+
+// Pathnames matching the pattern, in sorted order. Quoted characters must
+// have been escaped, so that they only match themselves.
+pub fn expand_pathname(pattern: &str) -> Vec<String> {
+    let components: Vec<&str> = pattern.split('/').collect();
+    let mut paths = Vec::new();
+    expand_components("", &components, true, &mut paths);
+    paths.sort();
+    paths
+}
+
+#[cfg(test)]
+mod test {
+    use super::expand_pathname;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    fn create_tree(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("vish-glob-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src/sub")).unwrap();
+        for file in ["b.o", "a.o", "c.rs", ".hidden.o", "src/main.rs", "src/sub/x.rs", "[x]"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        root
+    }
+
+    fn expand(root: &Path, pattern: &str) -> Vec<String> {
+        let root = root.to_str().unwrap();
+        expand_pathname(&format!("{}/{}", root, pattern))
+            .into_iter()
+            .map(|path| String::from(&path[root.len() + 1..]))
+            .collect()
+    }
+
+    #[test]
+    fn match_sorted_pathnames() {
+        let root = create_tree("sorted");
+        assert_eq!(expand(&root, "*.o"), vec!["a.o", "b.o"]);
+        assert_eq!(expand(&root, "?.[or]*"), vec!["a.o", "b.o", "c.rs"]);
+        assert_eq!(expand(&root, "*/*.rs"), vec!["src/main.rs"]);
+        assert_eq!(expand(&root, "src/*/"), vec!["src/sub/"]);
+        assert_eq!(expand(&root, "*.none"), Vec::<String>::new());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn match_leading_dot_explicitly() {
+        let root = create_tree("dot");
+        assert_eq!(expand(&root, ".*.o"), vec![".hidden.o"]);
+        assert!(!expand(&root, "*").contains(&String::from(".hidden.o")));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn match_escaped_characters_literally() {
+        let root = create_tree("escaped");
+        assert_eq!(expand(&root, "\\[x]"), vec!["[x]"]);
+        assert_eq!(expand(&root, "\\*.o"), Vec::<String>::new());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
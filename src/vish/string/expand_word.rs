@@ -0,0 +1,557 @@
+use crate::vish::environment::ShellEnvironment as Env;
+use crate::vish::parser::expansion_length;
+use crate::vish::pattern::{self, Pattern};
+use super::{Field, replace_tilde, expand_pathname};
+use super::expand_parameter::{expand_braces, Braces};
+
+const DEFAULT_IFS: &str = " \t\n";
+
+// Part of a word as written, before any expansion
+#[derive(Debug, PartialEq)]
+enum Segment {
+    // Unquoted text, with any backslash still in place
+    Literal(String),
+    SingleQuoted(String),
+    // Text between double quotes, with any backslash still in place
+    DoubleQuoted(String),
+    Expansion(String, bool),
+}
+
+// Text produced by a segment, remembering where it came from. Quotes and
+// backslashes are only removed once the word has been expanded.
+#[derive(Debug, PartialEq)]
+enum Piece {
+    Literal(String),
+    SingleQuoted(String),
+    DoubleQuoted(String),
+    Expanded(String, bool),
+    // Positional parameters from $@, or from $* when unquoted, each one
+    // giving a separate field
+    Parameters(Vec<String>, bool),
+}
+
+// Unquoted, a backslash preserves the next character, while inside double
+// quotes it only does so for the characters that would be special there
+fn remove_backslashes(text: &str, double_quoted: bool) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some('$' | '`' | '"' | '\\') if c == '\\' && double_quoted => {
+                result.extend(chars.next());
+            },
+            Some(_) if c == '\\' && !double_quoted => result.extend(chars.next()),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+impl Piece {
+    fn is_quoted(&self) -> bool {
+        !matches!(self,
+            Piece::Literal(_) | Piece::Expanded(_, false) | Piece::Parameters(_, false))
+    }
+
+    // Quote removal, the last stage of the expansion of a word
+    fn text(&self) -> String {
+        match self {
+            Piece::Literal(text) => remove_backslashes(text, false),
+            Piece::DoubleQuoted(text) => remove_backslashes(text, true),
+            Piece::SingleQuoted(text) | Piece::Expanded(text, _) => text.clone(),
+            Piece::Parameters(values, _) => values.join(" "),
+        }
+    }
+
+    // The text as part of a pattern, where quoted characters aren't special
+    fn pattern(&self) -> String {
+        match self {
+            Piece::Literal(text) | Piece::Expanded(text, false) => text.clone(),
+            Piece::Parameters(_, false) => self.text(),
+            piece => pattern::escape(&piece.text()),
+        }
+    }
+}
+
+// A field resulting from splitting, along with its pattern for pathname
+// expansion
+struct SplitField {
+    text: String,
+    pattern: String,
+}
+
+fn read_expansion(chars: &[char], i: usize) -> Option<String> {
+    let rest: String = chars[i..].iter().collect();
+    let size = expansion_length(&rest);
+    if size > 1 {
+        Some(chars[i..i + size].iter().collect())
+    } else {
+        None
+    }
+}
+
+// Read the text inside double quotes, up to the closing quote, returning
+// where it ends. A word that is double-quoted as a whole, like the one in
+// "${x:-word}", is read to its end, and quotes inside it only group text.
+fn read_double_quoted(chars: &[char], mut i: usize, segments: &mut Vec<Segment>,
+    whole_word: bool) -> usize {
+    let mut quoted = String::new();
+    while i < chars.len() {
+        if chars[i] == '"' && !whole_word {
+            break;
+        } else if chars[i] == '"' {
+            i += 1;
+        } else if chars[i] == '\\' {
+            quoted.extend(&chars[i..(i + 2).min(chars.len())]);
+            i += 2;
+        } else if let Some(text) = read_expansion(chars, i) {
+            i += text.chars().count();
+            segments.push(Segment::DoubleQuoted(std::mem::take(&mut quoted)));
+            segments.push(Segment::Expansion(text, true));
+        } else {
+            quoted.push(chars[i]);
+            i += 1;
+        }
+    }
+    segments.push(Segment::DoubleQuoted(quoted));
+    i
+}
+
+// Split a word from the tokenizer into quoted, unquoted and expansion parts
+fn parse_segments(word: &str, double_quoted: bool) -> Vec<Segment> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut segments = Vec::new();
+    if double_quoted {
+        read_double_quoted(&chars, 0, &mut segments, true);
+        return segments;
+    }
+    let mut literal = String::new();
+    let mut i = 0;
+
+    macro_rules! flush_literal {
+        () => {
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(std::mem::take(&mut literal)));
+            }
+        };
+    }
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => {
+                literal.extend(&chars[i..(i + 2).min(chars.len())]);
+                i += 2;
+            },
+            '\'' => {
+                flush_literal!();
+                let end = chars[i + 1..].iter()
+                    .position(|c| *c == '\'')
+                    .map_or(chars.len(), |pos| i + 1 + pos);
+                segments.push(Segment::SingleQuoted(chars[i + 1..end].iter().collect()));
+                i = end + 1;
+            },
+            '"' => {
+                flush_literal!();
+                i = read_double_quoted(&chars, i + 1, &mut segments, false) + 1;
+            },
+            // ANSI-C quoting, decoded like an expansion whose result is quoted
+            '$' if chars.get(i + 1) == Some(&'\'') => {
+                flush_literal!();
+                let mut end = i + 2;
+                while end < chars.len() && chars[end] != '\'' {
+                    end += if chars[end] == '\\' { 2 } else { 1 };
+                }
+                let end = end.min(chars.len() - 1);
+                segments.push(Segment::Expansion(chars[i..=end].iter().collect(), true));
+                i = end + 1;
+            },
+            '$' | '`' => match read_expansion(&chars, i) {
+                Some(text) => {
+                    flush_literal!();
+                    i += text.chars().count();
+                    segments.push(Segment::Expansion(text, false));
+                },
+                None => {
+                    literal.push(chars[i]);
+                    i += 1;
+                },
+            },
+            c => {
+                literal.push(c);
+                i += 1;
+            },
+        }
+    }
+    flush_literal!();
+
+    segments
+}
+
+// A leading unquoted tilde prefix names a home directory. Its expansion is
+// treated as quoted, so that it is neither split nor used as a pattern.
+fn expand_tilde(segments: &mut [Segment], pieces: &mut Vec<Piece>, env: &Env) {
+    let is_single = segments.len() == 1;
+    let Some(Segment::Literal(text)) = segments.first() else { return };
+    let end = text.find('/').unwrap_or(text.len());
+    let prefix = &text[..end];
+    if !prefix.starts_with('~') || prefix.contains('\\') || (end == text.len() && !is_single) {
+        return;
+    }
+
+    let home = replace_tilde(String::from(prefix), env);
+    if home != prefix {
+        let rest = String::from(&text[end..]);
+        pieces.push(Piece::Expanded(home, true));
+        segments[0] = Segment::Literal(rest);
+    }
+}
+
+fn is_all_parameters(text: &str, quoted: bool) -> bool {
+    matches!(text, "$@" | "${@}") || (!quoted && matches!(text, "$*" | "${*}"))
+}
+
+fn expand_segments(word: &str, double_quoted: bool, env: &mut Env) -> Vec<Piece> {
+    let mut segments = parse_segments(word, double_quoted);
+    let mut pieces = Vec::with_capacity(segments.len());
+    expand_tilde(&mut segments, &mut pieces, env);
+
+    for segment in segments {
+        match segment {
+            Segment::Literal(text) => pieces.push(Piece::Literal(text)),
+            Segment::SingleQuoted(text) => pieces.push(Piece::SingleQuoted(text)),
+            Segment::DoubleQuoted(text) => pieces.push(Piece::DoubleQuoted(text)),
+            Segment::Expansion(text, quoted) if is_all_parameters(&text, quoted) => {
+                pieces.push(Piece::Parameters(env.positional_parameters.clone(), quoted));
+            },
+            // The word after an operator keeps its own quotes, and is taken
+            // as double-quoted when the whole expansion is
+            Segment::Expansion(text, quoted) if text.starts_with("${") => {
+                match expand_braces(&text[2..text.len() - 1], quoted, env) {
+                    Braces::Value(value) => pieces.push(Piece::Expanded(value, quoted)),
+                    Braces::Word(word) => pieces.extend(expand_segments(word, quoted, env)),
+                }
+            },
+            Segment::Expansion(text, quoted) => {
+                let field = if quoted { Field::new_quoted(text) } else { Field::new(text) };
+                pieces.push(Piece::Expanded(field.substitute(env), quoted));
+            },
+        }
+    }
+
+    // Without positional parameters, "$@" gives no field at all, even
+    // though it is quoted
+    let is_empty = |piece: &Piece| match piece {
+        Piece::Parameters(values, true) => values.is_empty(),
+        Piece::DoubleQuoted(text) => text.is_empty(),
+        _ => false,
+    };
+    if pieces.iter().any(|piece| matches!(piece, Piece::Parameters(_, true)))
+        && pieces.iter().all(is_empty) {
+        pieces.clear();
+    }
+    pieces
+}
+
+fn is_ifs_whitespace(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\n'
+}
+
+// Results of unquoted expansions are split where IFS characters occur. IFS
+// whitespace at the edges is ignored, and any other IFS character, together
+// with the whitespace around it, delimits exactly one field.
+fn split_fields(pieces: Vec<Piece>, ifs: &str) -> Vec<SplitField> {
+    let mut fields = Vec::new();
+    let mut current = SplitField { text: String::new(), pattern: String::new() };
+    let mut has_field = false;
+    // Whether the last field ended at IFS whitespace, which may still be
+    // followed by a non-whitespace separator belonging to the same delimiter
+    let mut after_whitespace = false;
+
+    macro_rules! push_field {
+        () => {
+            fields.push(std::mem::replace(&mut current,
+                SplitField { text: String::new(), pattern: String::new() }));
+        };
+    }
+
+    for piece in pieces {
+        let values = match piece {
+            Piece::Expanded(text, false) => vec![text],
+            Piece::Parameters(values, false) => values,
+            Piece::Parameters(values, true) => {
+                for (i, value) in values.into_iter().enumerate() {
+                    if i > 0 {
+                        push_field!();
+                    }
+                    current.pattern.push_str(&pattern::escape(&value));
+                    current.text.push_str(&value);
+                    has_field = true;
+                    after_whitespace = false;
+                }
+                continue;
+            },
+            piece => {
+                let text = piece.text();
+                if piece.is_quoted() || !text.is_empty() {
+                    has_field = true;
+                    after_whitespace = false;
+                }
+                current.pattern.push_str(&piece.pattern());
+                current.text.push_str(&text);
+                continue;
+            },
+        };
+
+        for (i, text) in values.into_iter().enumerate() {
+            // Each positional parameter ends the field of the previous one
+            if i > 0 && has_field {
+                push_field!();
+                has_field = false;
+                after_whitespace = false;
+            }
+            for c in text.chars() {
+                if !ifs.contains(c) {
+                    current.text.push(c);
+                    current.pattern.push(c);
+                    has_field = true;
+                    after_whitespace = false;
+                } else if has_field {
+                    push_field!();
+                    has_field = false;
+                    after_whitespace = is_ifs_whitespace(c);
+                } else if !is_ifs_whitespace(c) {
+                    if !after_whitespace {
+                        push_field!();
+                    }
+                    after_whitespace = false;
+                }
+            }
+        }
+    }
+
+    if has_field {
+        push_field!();
+    }
+    fields
+}
+
+// Expand a word into a single field, as done for redirection targets
+pub fn expand_word(word: &str, env: &mut Env) -> String {
+    expand_segments(word, false, env)
+        .into_iter()
+        .map(|piece| piece.text())
+        .collect()
+}
+
+// Expand a word as if it were written between double quotes
+pub fn expand_quoted_word(word: &str, env: &mut Env) -> String {
+    expand_segments(word, true, env)
+        .into_iter()
+        .map(|piece| piece.text())
+        .collect()
+}
+
+// Expand a word used as a pattern, keeping quoted characters from being
+// treated as special by the pattern matcher
+pub fn expand_pattern(word: &str, env: &mut Env) -> String {
+    expand_segments(word, false, env)
+        .into_iter()
+        .map(|piece| piece.pattern())
+        .collect()
+}
+
+// Expand the words of a command into the list of fields it stands for.
+// Fields that are patterns are replaced by the pathnames they match, unless
+// there are none or set -f is in effect.
+pub fn expand_words(words: &[String], env: &mut Env) -> Vec<String> {
+    let ifs = env.get_var("IFS").unwrap_or_else(|| String::from(DEFAULT_IFS));
+    let noglob = env.options.contains(&'f');
+    let mut fields = Vec::new();
+
+    for word in words {
+        let pieces = expand_segments(word, false, env);
+        for field in split_fields(pieces, &ifs) {
+            if !noglob && Pattern::new(&field.pattern).has_wildcards() {
+                let paths = expand_pathname(&field.pattern);
+                if !paths.is_empty() {
+                    fields.extend(paths);
+                    continue;
+                }
+            }
+            fields.push(field.text);
+        }
+    }
+
+    fields
+}
+
+#[cfg(test)]
+mod expand_words {
+    use super::{expand_word, expand_words, expand_pattern};
+    use crate::vish::environment::ShellEnvironment as Env;
+    use crate::vish::parser::{parse, Command};
+
+    fn env() -> Env {
+        let mut env = Env::new();
+        env.set_var("HOME", String::from("/home/kevin")).unwrap();
+        env.set_var("IFS", String::from(" \t\n")).unwrap();
+        env.set_var("name", String::from("John Smith")).unwrap();
+        env.set_var("empty", String::new()).unwrap();
+        env
+    }
+
+    fn expand(words: &[&str]) -> Vec<String> {
+        let words: Vec<String> = words.iter().map(|w| w.to_string()).collect();
+        expand_words(&words, &mut env())
+    }
+
+    #[test]
+    fn expand_plain_word() {
+        assert_eq!(expand(&["hello"]), vec!["hello"]);
+    }
+
+    #[test]
+    fn expand_tilde() {
+        assert_eq!(expand(&["~"]), vec!["/home/kevin"]);
+        assert_eq!(expand(&["~/projects"]), vec!["/home/kevin/projects"]);
+        assert_eq!(expand(&["~john/backup/"]), vec!["/home/john/backup/"]);
+    }
+
+    #[test]
+    fn remove_quotes() {
+        assert_eq!(expand(&["\"hello world\"", "'test output.log'"]),
+            vec!["hello world", "test output.log"]);
+        assert_eq!(expand(&["\"it's\"", "'say \"hi\"'"]), vec!["it's", "say \"hi\""]);
+    }
+
+    #[test]
+    fn join_quoted_and_unquoted_parts() {
+        assert_eq!(expand(&["--name=\"John Smith\""]), vec!["--name=John Smith"]);
+    }
+
+    #[test]
+    fn join_adjacent_segments_of_parsed_words() {
+        let lists = parse("cmd --name=\"John Smith\" 'a'\"b\"c ${name}").unwrap();
+        let Command::Simple(command) = &lists[0].items[0].and_or.first.commands[0] else {
+            panic!("expected a simple command");
+        };
+        assert_eq!(expand_words(&command.words, &mut env()),
+            vec!["cmd", "--name=John Smith", "abc", "John", "Smith"]);
+    }
+
+    #[test]
+    fn do_not_expand_quoted_tilde() {
+        assert_eq!(expand(&["\"~john/file.txt\""]), vec!["~john/file.txt"]);
+    }
+
+    #[test]
+    fn split_unquoted_expansions() {
+        assert_eq!(expand(&["$name"]), vec!["John", "Smith"]);
+        assert_eq!(expand(&["a${name}b"]), vec!["aJohn", "Smithb"]);
+        assert_eq!(expand(&["\"$name\""]), vec!["John Smith"]);
+    }
+
+    #[test]
+    fn split_on_non_whitespace_separators() {
+        let mut env = env();
+        env.set_var("IFS", String::from(" :")).unwrap();
+        let mut split = |value: &str| {
+            env.set_var("value", String::from(value)).unwrap();
+            expand_words(&[String::from("$value")], &mut env)
+        };
+        assert_eq!(split("a::b"), vec!["a", "", "b"]);
+        assert_eq!(split(" a : b "), vec!["a", "b"]);
+        assert_eq!(split("a: :b"), vec!["a", "", "b"]);
+        assert_eq!(split(":a:"), vec!["", "a"]);
+        assert_eq!(split("  "), Vec::<String>::new());
+    }
+
+    #[test]
+    fn keep_words_together_with_empty_ifs() {
+        let mut env = env();
+        env.set_var("IFS", String::new()).unwrap();
+        assert_eq!(expand_words(&[String::from("$name")], &mut env), vec!["John Smith"]);
+    }
+
+    #[test]
+    fn remove_empty_unquoted_expansions() {
+        assert_eq!(expand(&["$empty", "$unset_variable", "x"]), vec!["x"]);
+        assert_eq!(expand(&["\"$empty\"", "''"]), vec!["", ""]);
+    }
+
+    #[test]
+    fn keep_single_quoted_expansions() {
+        assert_eq!(expand(&["'$name'"]), vec!["$name"]);
+    }
+
+    #[test]
+    fn remove_backslashes() {
+        assert_eq!(expand(&["a\\ b", "\\$name", "\\\\"]), vec!["a b", "$name", "\\"]);
+        assert_eq!(expand(&["\"say \\\"hi\\\"\""]), vec!["say \"hi\""]);
+        assert_eq!(expand(&["\"\\$name \\a \\\\\""]), vec!["$name \\a \\"]);
+        assert_eq!(expand(&["'\\n'"]), vec!["\\n"]);
+    }
+
+    #[test]
+    fn do_not_remove_quotes_from_expansions() {
+        let mut env = env();
+        env.set_var("quotes", String::from("'a' \\b")).unwrap();
+        assert_eq!(expand_words(&[String::from("$quotes")], &mut env), vec!["'a'", "\\b"]);
+    }
+
+    #[test]
+    fn decode_ansi_c_quotes() {
+        assert_eq!(expand(&["$'a b\\t'c", "\"$'x'\""]), vec!["a b\tc", "$'x'"]);
+        assert_eq!(expand(&["$'it\\'s'"]), vec!["it's"]);
+    }
+
+    #[test]
+    fn expand_positional_parameters() {
+        let mut env = env();
+        env.positional_parameters = vec![String::from("a b"), String::new(), String::from("c")];
+        let mut expand = |word: &str| expand_words(&[String::from(word)], &mut env);
+        assert_eq!(expand("\"$@\""), vec!["a b", "", "c"]);
+        assert_eq!(expand("\"x$@y\""), vec!["xa b", "", "cy"]);
+        assert_eq!(expand("$@"), vec!["a", "b", "c"]);
+        assert_eq!(expand("$*"), vec!["a", "b", "c"]);
+        assert_eq!(expand("\"$*\""), vec!["a b  c"]);
+        assert_eq!(expand("$#"), vec!["3"]);
+
+        env.set_var("IFS", String::from(":")).unwrap();
+        assert_eq!(expand_words(&[String::from("\"$*\"")], &mut env), vec!["a b::c"]);
+    }
+
+    #[test]
+    fn remove_quoted_parameters_when_there_are_none() {
+        let mut env = env();
+        assert_eq!(expand_words(&[String::from("\"$@\"")], &mut env), Vec::<String>::new());
+        assert_eq!(expand_words(&[String::from("\"x$@\"")], &mut env), vec!["x"]);
+        assert_eq!(expand_words(&[String::from("\"$*\"")], &mut env), vec![""]);
+    }
+
+    #[test]
+    fn keep_quotes_of_words_after_operators() {
+        assert_eq!(expand(&["${nope:-\"a b\"}"]), vec!["a b"]);
+        assert_eq!(expand(&["${nope:-$name}"]), vec!["John", "Smith"]);
+        assert_eq!(expand(&["${name:+'x y'}z"]), vec!["x yz"]);
+    }
+
+    #[test]
+    fn keep_single_quotes_in_double_quoted_words() {
+        assert_eq!(expand(&["\"${nope:-'q'}\""]), vec!["'q'"]);
+        assert_eq!(expand(&["\"${nope:-\"a'b\"}\""]), vec!["a'b"]);
+        assert_eq!(expand(&["\"${nope:-$name}\""]), vec!["John Smith"]);
+    }
+
+    #[test]
+    fn expand_without_splitting() {
+        assert_eq!(expand_word("$name.txt", &mut env()), "John Smith.txt");
+    }
+
+    #[test]
+    fn escape_quoted_pattern_characters() {
+        assert_eq!(expand_pattern("*.txt", &mut env()), "*.txt");
+        assert_eq!(expand_pattern("'*'.txt", &mut env()), "\\*.txt");
+        assert_eq!(expand_pattern("\"$name?\"", &mut env()), "John Smith\\?");
+    }
+}
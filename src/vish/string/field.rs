@@ -1,5 +1,6 @@
-#[cfg(not(test))]
-use std::env::var as get_var;
+use crate::vish::environment::ShellEnvironment as Env;
+use super::{substitute_command, evaluate_arithmetic, expand_here_doc, expand_parameter};
+use super::expand_parameter::parameter_value;
 
 #[derive(Debug, PartialEq)]
 pub enum Field<T> {
@@ -66,35 +67,132 @@ fn is_special_parameter(text: &str) -> bool {
         special_chars.contains(&text.chars().nth(1).unwrap_or('_'))
 }
 
-fn get_parameter_name(text: String) -> (String, String) {
-    let mut read_text = String::with_capacity(text.len());
-    let mut longest_match: Option<usize> = None;
-
-    for (i, character) in text.chars().enumerate() {
-        if character.is_ascii_alphanumeric() || character == '_' {
-            read_text.push(character);
-            if get_var(&read_text).is_ok() {
-                longest_match = Some(i + 1);
-            }
-        } else {
+fn is_positional_parameter(text: &str) -> bool {
+    text.len() == 2 && text.starts_with('$') &&
+        text[1..].chars().all(|c| c.is_ascii_digit())
+}
+
+// Inside backquotes, a backslash only escapes another backslash, a dollar
+// sign or a backquote, and also a double quote when the backquotes are
+// themselves inside double quotes
+fn remove_backquote_escapes(text: &str, double_quoted: bool) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some('\\' | '$' | '`') if c == '\\' => {
+                result.extend(chars.next());
+            },
+            Some('"') if c == '\\' && double_quoted => {
+                result.extend(chars.next());
+            },
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+fn read_digits(chars: &[char], i: usize, radix: u32, max: usize) -> (u32, usize) {
+    let count = chars[i..].iter().take(max).take_while(|c| c.is_digit(radix)).count();
+    let digits: String = chars[i..i + count].iter().collect();
+    (u32::from_str_radix(&digits, radix).unwrap_or(0), count)
+}
+
+// Decode the backslash escapes of ANSI-C quoting, as in $'a\tb'. The string
+// ends early at a null byte, which can't be part of an argument.
+fn replace_ansi_c_escapes(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut bytes: Vec<u8> = Vec::with_capacity(text.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        if chars[i] != '\\' || i + 1 == chars.len() {
+            let mut buf = [0; 4];
+            bytes.extend(chars[i].encode_utf8(&mut buf).as_bytes());
+            i += 1;
+            continue;
+        }
+
+        let escape = chars[i + 1];
+        i += 2;
+        let byte = match escape {
+            'a' => 0x07,
+            'b' => 0x08,
+            'e' | 'E' => 0x1b,
+            'f' => 0x0c,
+            'n' => b'\n',
+            'r' => b'\r',
+            't' => b'\t',
+            'v' => 0x0b,
+            '\\' | '\'' | '"' | '?' => escape as u8,
+            'c' if i < chars.len() => {
+                i += 1;
+                (chars[i - 1] as u8) & 0x1f
+            },
+            'x' => {
+                let (value, count) = read_digits(&chars, i, 16, 2);
+                if count == 0 {
+                    bytes.extend(b"\\x");
+                    continue;
+                }
+                i += count;
+                value as u8
+            },
+            '0'..='7' => {
+                let (value, count) = read_digits(&chars, i - 1, 8, 3);
+                i += count - 1;
+                value as u8
+            },
+            'u' | 'U' => {
+                let max = if escape == 'u' { 4 } else { 8 };
+                let (value, count) = read_digits(&chars, i, 16, max);
+                i += count;
+                match char::from_u32(value).filter(|_| count > 0) {
+                    Some(c) => {
+                        let mut buf = [0; 4];
+                        bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+                    },
+                    None => bytes.extend(format!("\\{}", escape).as_bytes()),
+                }
+                continue;
+            },
+            c => {
+                bytes.push(b'\\');
+                let mut buf = [0; 4];
+                bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+                continue;
+            },
+        };
+        if byte == 0 {
             break;
         }
+        bytes.push(byte);
     }
 
-    if let Some(len) = longest_match {
-        let name = String::from(&text[..len]);
-        let remaining_chars = String::from(&text[len..]);
-        return (name, remaining_chars);
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// Split the longest valid name from the start of the text
+fn get_parameter_name(text: String) -> (String, String) {
+    if text.starts_with(|c: char| c.is_ascii_digit()) {
+        return (String::with_capacity(0), text);
     }
 
-    (String::with_capacity(0), text)
+    let len = text
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(text.len());
+    let name = String::from(&text[..len]);
+    let remaining_chars = String::from(&text[len..]);
+    (name, remaining_chars)
 }
 
 impl Field<String> {
     pub fn new(text: String) -> Field<String> {
         let size: usize = text.len();
 
-        if ! text.starts_with('$') {
+        if in_backticks(&text) {
+            Field::Command(remove_backquote_escapes(&text[1..size - 1], false))
+        } else if ! text.starts_with('$') {
             Field::Plain(text)
         } else if is_special_parameter(&text) {
             let name = text.chars().nth(1);
@@ -104,70 +202,89 @@ impl Field<String> {
             let mut par = String::new();
             par.push(name.unwrap());
             Field::Special(par)
+        } else if is_positional_parameter(&text) {
+            Field::Position(String::from(&text[1..]))
         } else if is_unenclosed_parameter(&text) {
             let (par, _rest) = get_parameter_name(String::from(&text[1..]));
             Field::Parameter(par)
-        } else if in_braces(&text) && !is_number(&text) {
+        } else if in_braces(&text) && !is_number(&text[2..size - 1]) {
             let par = String::from(&text[2..size - 1]);
             Field::Parameter(par)
-        } else if in_braces(&text) && is_number(&text) {
-            let num = String::from(text[2..size - 1].trim_start_matches('0'));
-            Field::Position(num)
+        } else if in_braces(&text) && size > 3 {
+            let num = text[2..size - 1].trim_start_matches('0');
+            Field::Position(String::from(if num.is_empty() { "0" } else { num }))
         } else if in_single_parenthesis(&text) {
             let command = String::from(&text[2..size - 1]);
             Field::Command(command)
-        } else if in_backticks(&text) {
-            let command = String::from(&text[1..size - 1]);
-            Field::Command(command)
         } else if in_double_parenthesis(&text) {
             let math = String::from(&text[3..size - 2]);
             Field::Arithmetic(math)
         } else if in_single_quotes(&text) {
-            let text = String::from(&text[1..size - 1]);
+            let text = String::from(&text[2..size - 1]);
             Field::Quoted(text)
         } else {
             Field::Plain(text)
         }
     }
 
-    pub fn substitute(self) -> String {
+    // Same as new, for an expansion written between double quotes
+    pub fn new_quoted(text: String) -> Field<String> {
+        if in_backticks(&text) {
+            Field::Command(remove_backquote_escapes(&text[1..text.len() - 1], true))
+        } else {
+            Field::new(text)
+        }
+    }
+
+    pub fn substitute(self, env: &mut Env) -> String {
         match &self {
             Field::Plain(text) => text.to_string(),
-            Field::Parameter(_) => self.substitute_parameter(),
-            Field::Command(text) => format!("command: {}", text),
-            Field::Arithmetic(text) => format!("arithmetic: {}", text),
-            Field::Quoted(text) => format!("quoted: {}", text),
-            Field::Position(text) => format!("positional parameter: {}", text),
-            Field::Special(text) => format!("special parameter: {}", text),
+            Field::Parameter(_) => self.substitute_parameter(env),
+            Field::Command(text) => substitute_command(text.to_string(), env),
+            Field::Arithmetic(_) => self.substitute_arithmetic(env),
+            Field::Quoted(text) => replace_ansi_c_escapes(text),
+            Field::Position(_) | Field::Special(_) => self.look_up_parameter(env),
         }
     }
 
-    fn substitute_parameter(self) -> String {
+    // Parameters in braces may be followed by an operator and a word
+    fn substitute_parameter(self, env: &mut Env) -> String {
         if let Field::Parameter(text) = self {
-            match get_var(text.as_str()) {
-                Ok(value) => value,
-                Err(_) => String::with_capacity(0),
-            }
+            expand_parameter(format!("${{{}}}", text), env)
         } else {
             String::with_capacity(0)
         }
     }
-}
 
-#[cfg(test)]
-use std::env::VarError;
+    // The expression is expanded as if it were in double quotes, which is
+    // the same as done for here-documents
+    fn substitute_arithmetic(self, env: &mut Env) -> String {
+        let Field::Arithmetic(text) = self else { return String::with_capacity(0) };
+        let expression = expand_here_doc(&text, env);
+        match evaluate_arithmetic(&expression, env) {
+            Ok(value) => value.to_string(),
+            Err(msg) => {
+                eprintln!("vish: arithmetic expression: {}: \"{}\"", msg, expression.trim());
+                env.expansion_failed = true;
+                String::with_capacity(0)
+            },
+        }
+    }
 
-#[cfg(test)]
-fn get_var(name: &str) -> Result<String, VarError> {
-    match name {
-        "HOME" => Ok("/home/gustav".to_string()),
-        _ => Err(VarError::NotPresent)
+    fn look_up_parameter(self, env: &Env) -> String {
+        match self {
+            Field::Position(num) | Field::Special(num) => {
+                parameter_value(&num, env).unwrap_or_default()
+            },
+            _ => String::with_capacity(0),
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::Field;
+    use crate::vish::environment::ShellEnvironment as Env;
 
     #[test]
     fn should_pass() {
@@ -176,4 +293,73 @@ mod test {
             Field::Parameter(String::from("HOME")),
         );
     }
+
+    #[test]
+    fn take_whole_parameter_name() {
+        assert_eq!(
+            Field::new(String::from("$HOME_DIR")),
+            Field::Parameter(String::from("HOME_DIR")),
+        );
+    }
+
+    #[test]
+    fn recognize_positional_parameters() {
+        assert_eq!(
+            Field::new(String::from("$1")),
+            Field::Position(String::from("1")),
+        );
+        assert_eq!(
+            Field::new(String::from("${10}")),
+            Field::Position(String::from("10")),
+        );
+    }
+
+    #[test]
+    fn remove_backquote_escapes() {
+        assert_eq!(
+            Field::new(String::from("`echo \\`date\\` \\$HOME \\\\ \\n`")),
+            Field::Command(String::from("echo `date` $HOME \\ \\n")),
+        );
+        assert_eq!(
+            Field::new(String::from("$(echo `date`)")),
+            Field::Command(String::from("echo `date`")),
+        );
+    }
+
+    #[test]
+    fn remove_escapes_of_double_quotes_when_quoted() {
+        assert_eq!(
+            Field::new_quoted(String::from("`echo \\\"hi\\\"`")),
+            Field::Command(String::from("echo \"hi\"")),
+        );
+        assert_eq!(
+            Field::new(String::from("`echo \\\"hi\\\"`")),
+            Field::Command(String::from("echo \\\"hi\\\"")),
+        );
+    }
+
+    #[test]
+    fn replace_ansi_c_escapes() {
+        let mut env = Env::new();
+        assert_eq!(
+            Field::new(String::from("$'a\\tb\\n'")).substitute(&mut env),
+            "a\tb\n",
+        );
+        assert_eq!(
+            Field::new(String::from("$'\\e[1m\\x41\\101\\u00e9'")).substitute(&mut env),
+            "\x1b[1mAA\u{e9}",
+        );
+        assert_eq!(
+            Field::new(String::from("$'\\cA\\\\\\'\\\"'")).substitute(&mut env),
+            "\x01\\'\"",
+        );
+        assert_eq!(
+            Field::new(String::from("$'\\q\\xg'")).substitute(&mut env),
+            "\\q\\xg",
+        );
+        assert_eq!(
+            Field::new(String::from("$'cut\\0here'")).substitute(&mut env),
+            "cut",
+        );
+    }
 }
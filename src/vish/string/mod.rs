@@ -1,10 +1,8 @@
 #[cfg(not(test))]
 use crate::vish::passwd::get_home;
 
-#[cfg(not(test))]
-use std::env::var as get_var;
-
-use crate::vish::command::ArgV;
+use crate::vish::parser::expansion_length;
+use crate::vish::environment::ShellEnvironment as Env;
 
 mod expand_parameter;
 pub use expand_parameter::expand_parameter;
@@ -12,22 +10,31 @@ pub use expand_parameter::expand_parameter;
 mod substitute_command;
 pub use substitute_command::substitute_command;
 
+mod evaluate_arithmetic;
+pub use evaluate_arithmetic::evaluate_arithmetic;
+
+mod expand_pathname;
+pub use expand_pathname::expand_pathname;
+
 mod field;
 pub use field::Field;
 
-pub fn replace_tilde(user_input: String) -> String {
+mod expand_word;
+pub use expand_word::{expand_word, expand_quoted_word, expand_words, expand_pattern};
+
+pub fn replace_tilde(user_input: String, env: &Env) -> String {
     let tilde_index = user_input.find('~');
     let bar_index = user_input.find('/');
     if tilde_index.is_none() {
         user_input
     } else if user_input == "~" || user_input.find("~/") == Some(0) {
-        match get_var("HOME") {
-            Ok(home) => {
+        match env.get_var("HOME") {
+            Some(home) => {
                 let mut output = user_input.clone();
                 output.replace_range(0..1, home.as_str());
                 output
             },
-            Err(_) => user_input,
+            None => user_input,
         }
     } else if tilde_index == Some(0) && bar_index != Some(1) {
         let user = match bar_index {
@@ -54,104 +61,36 @@ pub fn replace_tilde(user_input: String) -> String {
     }
 }
 
-pub fn parse_argv(text: &str) -> (ArgV, Option<char>) {
-    let mut argv: ArgV = Vec::new();
-    let mut in_quotes = false;
-    let mut current_arg = String::new();
-    let mut quote_char = '\0';
-
-    for c in text.chars() {
-        match c {
-            '\'' | '"' if !in_quotes => {
-                in_quotes = true;
-                quote_char = c;
+// Expand the body of a here-document whose delimiter wasn't quoted. Only
+// backslashes before `$`, backquote, backslash and newline are special.
+pub fn expand_here_doc(body: &str, env: &mut Env) -> String {
+    let chars: Vec<char> = body.chars().collect();
+    let mut result = String::with_capacity(body.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        match (chars[i], chars.get(i + 1)) {
+            ('\\', Some('$')) | ('\\', Some('`')) | ('\\', Some('\\')) => {
+                result.push(chars[i + 1]);
+                i += 2;
             },
-            '\'' | '"' if in_quotes && c == quote_char => {
-                in_quotes = false;
-                argv.push(replace_tilde(current_arg.clone()));
-                current_arg.clear();
-            },
-            ' ' | '\t' if !in_quotes && !current_arg.is_empty() => {
-                argv.push(replace_tilde(current_arg.clone()));
-                current_arg.clear();
-            },
-            ' ' | '\t' if !in_quotes && current_arg.is_empty() => {},
-            _ => {
-                current_arg.push(c);
-            }
-        }
-    }
-
-    if !current_arg.is_empty() {
-        argv.push(replace_tilde(current_arg));
-    }
-
-    if in_quotes {
-        (argv, Some(quote_char))
-    } else {
-        (argv, None)
-    }
-}
-
-pub fn split_argv(text: &str) -> (Vec<String>, Option<char>) {
-    let mut argv: Vec<String> = Vec::new();
-    let mut in_quotes = false;
-    let mut current_arg = String::new();
-    let mut quote_char = '\0';
-
-    for c in text.chars() {
-        match c {
-            '\'' | '"' if !in_quotes => {
-                in_quotes = true;
-                quote_char = c;
-            },
-            '\'' | '"' if in_quotes && c == quote_char => {
-                in_quotes = false;
-                argv.push(Field::Plain(current_arg.clone()).substitute());
-                current_arg.clear();
-            },
-            ' ' | '\t' if !in_quotes && !current_arg.is_empty() => {
-                argv.push(Field::Plain(current_arg.clone()).substitute());
-                current_arg.clear();
+            ('\\', Some('\n')) => { i += 2; },
+            ('$', _) | ('`', _) => {
+                let text: String = chars[i..].iter().collect();
+                let size = expansion_length(&text);
+                if size > 1 {
+                    let expansion: String = chars[i..i + size].iter().collect();
+                    result.push_str(&Field::new(expansion).substitute(env));
+                } else {
+                    result.push(chars[i]);
+                }
+                i += size;
             },
-            ' ' | '\t' if !in_quotes && current_arg.is_empty() => {},
-            _ => {
-                current_arg.push(c);
-            }
+            (c, _) => { result.push(c); i += 1; },
         }
     }
 
-    if !current_arg.is_empty() {
-        argv.push(Field::Plain(current_arg).substitute());
-    }
-
-    if in_quotes {
-        (argv, Some(quote_char))
-    } else {
-        (argv, None)
-    }
-}
-
-#[cfg(test)]
-mod split_argv {
-    use super::{split_argv, Field};
-
-    #[test]
-    fn return_single_plain_field() {
-        let input = "antedeguemon";
-        let (output, _) = split_argv(input);
-        assert_eq!(output, vec![Field::Plain("antedeguemon".to_string()).substitute()]);
-    }
-
-    #[test]
-    fn return_parameter_field() {
-        let input = "echo ${USER}";
-        let (output, _) = split_argv(input);
-        assert_eq!(output, vec![
-            Field::Plain("echo".to_string()).substitute(),
-            Field::Parameter("USER".to_string()).substitute()
-        ]);
-    }
+    result
 }
 
 #[cfg(test)]
@@ -162,19 +101,21 @@ fn get_home(user: String) -> Option<String> {
     home_dirs.get(&user).cloned()
 }
 
-#[cfg(test)]
-fn get_var(_: &str) -> Result<String, std::env::VarError> {
-    Ok(String::from("/home/kevin"))
-}
-
 #[cfg(test)]
 mod replace_tilde {
     use super::replace_tilde;
+    use crate::vish::environment::ShellEnvironment as Env;
+
+    fn env() -> Env {
+        let mut env = Env::new();
+        env.set_var("HOME", String::from("/home/kevin")).unwrap();
+        env
+    }
 
     #[test]
     fn replace_single_tilde_with_home() {
         let input = String::from("~");
-        let output = replace_tilde(input.clone());
+        let output = replace_tilde(input.clone(), &env());
         let expected = "/home/kevin";
         assert_eq!(output, expected, "\n input: `{:?}`", input);
     }
@@ -182,7 +123,7 @@ mod replace_tilde {
     #[test]
     fn replace_single_tilde_with_home_subdir() {
         let input = String::from("~/.config");
-        let output = replace_tilde(input.clone());
+        let output = replace_tilde(input.clone(), &env());
         let expected = "/home/kevin/.config";
         assert_eq!(output, expected, "\n input: `{:?}`", input);
     }
@@ -190,7 +131,7 @@ mod replace_tilde {
     #[test]
     fn replace_tilde_for_user() {
         let input = String::from("~john");
-        let output = replace_tilde(input.clone());
+        let output = replace_tilde(input.clone(), &env());
         let expected = "/home/john";
         assert_eq!(output, expected, "\n input: `{:?}`", input);
     }
@@ -198,7 +139,7 @@ mod replace_tilde {
     #[test]
     fn replace_tilde_for_user_subdir() {
         let input = String::from("~john/.config");
-        let output = replace_tilde(input.clone());
+        let output = replace_tilde(input.clone(), &env());
         let expected = "/home/john/.config";
         assert_eq!(output, expected, "\n input: `{:?}`", input);
     }
@@ -206,7 +147,7 @@ mod replace_tilde {
     #[test]
     fn replace_tilde_for_root() {
         let input = String::from("~root");
-        let output = replace_tilde(input.clone());
+        let output = replace_tilde(input.clone(), &env());
         let expected = "/root";
         assert_eq!(output, expected, "\n input: `{:?}`", input);
     }
@@ -214,7 +155,7 @@ mod replace_tilde {
     #[test]
     fn replace_tilde_for_root_subdir() {
         let input = String::from("~root/.config");
-        let output = replace_tilde(input.clone());
+        let output = replace_tilde(input.clone(), &env());
         let expected = "/root/.config";
         assert_eq!(output, expected, "\n input: `{:?}`", input);
     }
@@ -222,89 +163,39 @@ mod replace_tilde {
     #[test]
     fn do_not_replace() {
         let input = String::from("/usr/local");
-        let output = replace_tilde(input.clone());
+        let output = replace_tilde(input.clone(), &env());
         let expected = "/usr/local";
         assert_eq!(output, expected, "\n input: `{:?}`", input);
     }
-}
-
-#[cfg(test)]
-mod parse_argv {
-    use super::parse_argv;
 
     #[test]
-    fn parse_argv_basic() {
-        let input = "echo hello world";
-        let expected = vec!["echo", "hello", "world"];
-        let (result, quote_char) = parse_argv(input);
-        assert_eq!(result, expected);
-        assert_eq!(quote_char, None);
-    }
-
-    #[test]
-    fn parse_argv_with_tilde() {
-        let input = "cd ~";
-        let expected = vec!["cd", "/home/kevin"];
-        let (result, quote_char) = parse_argv(input);
-        assert_eq!(result, expected);
-        assert_eq!(quote_char, None);
-    }
-
-    #[test]
-    fn parse_argv_with_tilde_and_path() {
-        let input = "cd ~/projects";
-        let expected = vec!["cd", "/home/kevin/projects"];
-        let (result, quote_char) = parse_argv(input);
-        assert_eq!(result, expected);
-        assert_eq!(quote_char, None);
-    }
-
-    #[test]
-    fn parse_argv_with_quoted_string() {
-        let input = "echo \"hello world\"";
-        let expected = vec!["echo", "hello world"];
-        let (result, quote_char) = parse_argv(input);
-        assert_eq!(result, expected);
-        assert_eq!(quote_char, None);
+    fn keep_tilde_without_home() {
+        let mut env = env();
+        env.unset_var("HOME").unwrap();
+        let input = String::from("~/.config");
+        let output = replace_tilde(input.clone(), &env);
+        assert_eq!(output, input);
     }
+}
 
-    #[test]
-    fn parse_argv_with_multiple_quoted_strings() {
-        let input = "echo \"hello world\" 'and universe'";
-        let expected = vec!["echo", "hello world", "and universe"];
-        let (result, quote_char) = parse_argv(input);
-        assert_eq!(result, expected);
-        assert_eq!(quote_char, None);
-    }
+#[cfg(test)]
+mod expand_here_doc {
+    use super::expand_here_doc;
+    use crate::vish::environment::ShellEnvironment as Env;
 
     #[test]
-    fn parse_argv_with_quotes_and_tilde() {
-        let input = "cp \"~john/file with spaces.txt\" ~john/backup/";
-        let expected = vec![
-            "cp",
-            "/home/john/file with spaces.txt",
-            "/home/john/backup/",
-        ];
-        let (result, quote_char) = parse_argv(input);
-        assert_eq!(result, expected);
-        assert_eq!(quote_char, None);
+    fn keep_plain_text() {
+        assert_eq!(expand_here_doc("a 'b' \"c\"\n", &mut Env::new()), "a 'b' \"c\"\n");
     }
 
     #[test]
-    fn parse_argv_with_single_quotes() {
-        let input = "touch 'test output.log'";
-        let expected = vec!["touch", "test output.log"];
-        let (result, quote_char) = parse_argv(input);
-        assert_eq!(result, expected);
-        assert_eq!(quote_char, None);
+    fn remove_escaping_backslashes() {
+        assert_eq!(expand_here_doc("\\$x \\` \\\\ \\a\n", &mut Env::new()), "$x ` \\ \\a\n");
+        assert_eq!(expand_here_doc("one \\\ntwo\n", &mut Env::new()), "one two\n");
     }
 
     #[test]
-    fn parse_argv_with_empty_string() {
-        let input = "";
-        let expected: Vec<String> = Vec::new();
-        let (result, quote_char) = parse_argv(input);
-        assert_eq!(result, expected);
-        assert_eq!(quote_char, None);
+    fn keep_lone_dollar_sign() {
+        assert_eq!(expand_here_doc("costs 5$ $\n", &mut Env::new()), "costs 5$ $\n");
     }
 }
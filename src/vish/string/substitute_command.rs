@@ -1,3 +1,66 @@
-pub fn substitute_command(_input: String) -> String {
-    todo!("Implement command substitution")
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::io::FromRawFd;
+
+use crate::vish::environment::ShellEnvironment as Env;
+use crate::vish::executor::{execute, process::{fork, exit_child, wait_for, pipe, move_fd, close}};
+use crate::vish::parser::parse;
+
+// Run the command in a subshell and replace it with what it writes to the
+// standard output, without the trailing newlines
+pub fn substitute_command(input: String, env: &mut Env) -> String {
+    let lists = match parse(&input) {
+        Ok(lists) => lists,
+        Err(e) => {
+            eprintln!("vish: {}", e);
+            env.last_status = 2;
+            env.expansion_failed = true;
+            return String::new();
+        },
+    };
+
+    let (read_end, write_end) = match pipe() {
+        Ok(fds) => fds,
+        Err(e) => {
+            eprintln!("vish: cannot create pipe: {}", e);
+            return String::new();
+        },
+    };
+
+    match fork() {
+        Ok(None) => {
+            close(read_end);
+            let _ = move_fd(write_end, 1);
+            let mut status = 0;
+            for list in &lists {
+                status = execute(list, env);
+                if env.interrupt.is_some() {
+                    break;
+                }
+            }
+            exit_child(status);
+        },
+        Ok(Some(pid)) => {
+            close(write_end);
+            let mut output = Vec::new();
+            let mut file = unsafe { File::from_raw_fd(read_end) };
+            if let Err(e) = file.read_to_end(&mut output) {
+                eprintln!("vish: cannot read command output: {}", e);
+            }
+            drop(file);
+            env.last_status = wait_for(pid);
+            env.substitution_status = Some(env.last_status);
+
+            while output.last() == Some(&b'\n') {
+                output.pop();
+            }
+            String::from_utf8_lossy(&output).into_owned()
+        },
+        Err(e) => {
+            close(read_end);
+            close(write_end);
+            eprintln!("vish: cannot fork: {}", e);
+            String::new()
+        },
+    }
 }